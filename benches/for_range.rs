@@ -0,0 +1,30 @@
+#![feature(plugin, test)]
+#![plugin(stateful)]
+
+extern crate test;
+
+use test::Bencher;
+
+#[generator]
+fn range_counter_literal() -> Box<Iterator<Item=usize>> {
+    for i in 0..1000 {
+        yield_!(i);
+    }
+}
+
+#[generator]
+fn range_into_iter_literal() -> Box<Iterator<Item=usize>> {
+    for i in ::std::iter::IntoIterator::into_iter(0..1000) {
+        yield_!(i);
+    }
+}
+
+#[bench]
+fn bench_range_counter_literal(b: &mut Bencher) {
+    b.iter(|| test::black_box(range_counter_literal()).sum::<usize>());
+}
+
+#[bench]
+fn bench_range_into_iter_literal(b: &mut Bencher) {
+    b.iter(|| test::black_box(range_into_iter_literal()).sum::<usize>());
+}
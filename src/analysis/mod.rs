@@ -0,0 +1,4 @@
+//! Analyses over the finished MIR, used to decide what the translation is allowed to emit.
+
+pub mod purity;
+pub mod unused_captures;
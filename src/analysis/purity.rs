@@ -0,0 +1,75 @@
+use mir::*;
+
+/// Conservatively determine if evaluating a state machine has no observable side effects besides
+/// producing its values. Since we only have the syntax to go on, anything that could run
+/// arbitrary code, like calls, method calls, and macros, makes the state machine impure. We also
+/// reject writes through a pointer or into a static.
+///
+/// The `on_yield` and `on_resume` hooks run arbitrary code too.
+///
+/// Note that overloaded operators are assumed to be pure.
+pub fn is_pure(mir: &Mir) -> bool {
+    if mir.options.on_yield.is_some() || mir.options.on_resume.is_some() {
+        return false;
+    }
+
+    for block_data in mir.basic_blocks() {
+        for statement in &block_data.statements {
+            let pure = match statement.kind {
+                StatementKind::Declare(_) |
+                StatementKind::Drop { .. } => true,
+                StatementKind::Let { ref rvalue, .. } => is_pure_rvalue(rvalue),
+                StatementKind::Assign(ref lvalue, ref rvalue) => {
+                    is_local_lvalue(lvalue) && is_pure_rvalue(rvalue)
+                }
+                StatementKind::Expr(_) |
+                StatementKind::Call { .. } |
+                StatementKind::MethodCall { .. } => false,
+            };
+
+            if !pure {
+                return false;
+            }
+        }
+
+        if let TerminatorKind::Suspend { ref rvalue, .. } = block_data.terminator().kind {
+            if !is_pure_rvalue(rvalue) {
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+fn is_pure_rvalue(rvalue: &Rvalue) -> bool {
+    match *rvalue {
+        Rvalue::Mac(_) |
+        Rvalue::Recompute(_) => false,
+        Rvalue::Use(_) |
+        Rvalue::Ref(..) |
+        Rvalue::BinaryOp(..) |
+        Rvalue::UnaryOp(..) |
+        Rvalue::Tuple(_) |
+        Rvalue::Struct(..) |
+        Rvalue::Range(..) |
+        Rvalue::Repeat(..) |
+        Rvalue::Type(..) |
+        Rvalue::Closure(_) => true,
+    }
+}
+
+fn is_local_lvalue(lvalue: &Lvalue) -> bool {
+    match *lvalue {
+        Lvalue::Local(_) => true,
+        Lvalue::Static(_) => false,
+        Lvalue::Projection(ref projection) => {
+            match projection.elem {
+                ProjectionElem::Deref => false,
+                ProjectionElem::Field(_) |
+                ProjectionElem::TupleField(_) |
+                ProjectionElem::Index(_) => is_local_lvalue(&projection.base),
+            }
+        }
+    }
+}
@@ -0,0 +1,243 @@
+use data_structures::indexed_vec::Idx;
+use mir::*;
+use std::collections::HashSet;
+use syntax::ast;
+use syntax::codemap::Span;
+use syntax::ext::base::ExtCtxt;
+use syntax::parse::token::Token;
+use syntax::tokenstream::TokenTree;
+use syntax::visit;
+
+/// Warn about user declared locals that are carried across a suspension point into the resumed
+/// state, but are never read again. These just take up space in the state machine, and usually
+/// mean the local is dead code.
+///
+/// Since parts of the MIR are still opaque syntax, we only know the names that are mentioned in a
+/// block. This errs on the side of not warning when a shadowed local shares a name with one that
+/// is used. Locals that start with an underscore are not reported.
+pub fn warn_unused_captures(cx: &ExtCtxt, mir: &Mir) {
+    let basic_blocks = mir.basic_blocks();
+
+    let names_used: Vec<HashSet<ast::Name>> = basic_blocks.iter()
+        .map(|block_data| names_used_in_block(mir, block_data))
+        .collect();
+
+    let mut reported = HashSet::new();
+
+    for block_data in basic_blocks {
+        let target = match block_data.terminator().kind {
+            TerminatorKind::Suspend { target, .. } => target,
+            _ => { continue; }
+        };
+
+        // Collect every name used by a block reachable from the resumed state.
+        let mut used = HashSet::new();
+        let mut visited = HashSet::new();
+        let mut worklist = vec![target];
+
+        while let Some(block) = worklist.pop() {
+            if !visited.insert(block) {
+                continue;
+            }
+
+            used.extend(names_used[block.index()].iter().cloned());
+            worklist.extend(basic_blocks[block].terminator().successors());
+        }
+
+        for live_decl in mir[target].incoming_decls.values().flat_map(|decls| decls) {
+            let local = match *live_decl {
+                LiveDecl::Active(local) => local,
+                LiveDecl::Moved(_) => { continue; }
+            };
+
+            let local_decl = &mir.local_decls[local];
+
+            if local == RETURN_POINTER || is_unreported_name(local_decl.name) {
+                continue;
+            }
+
+            if !used.contains(&local_decl.name.name) && reported.insert(local) {
+                cx.span_warn(
+                    local_decl.source_info.span,
+                    &format!("`{}` is captured across a suspension point but never used \
+                              afterwards",
+                             local_decl.name));
+            }
+        }
+    }
+}
+
+/// Temporaries and locals starting with an underscore are never reported.
+fn is_unreported_name(name: ast::Ident) -> bool {
+    let name = name.name.as_str();
+    name.starts_with('_') || name.starts_with("temp_")
+}
+
+fn names_used_in_block(mir: &Mir, block_data: &BasicBlockData) -> HashSet<ast::Name> {
+    let mut visitor = NameVisitor {
+        mir: mir,
+        names: HashSet::new(),
+    };
+
+    for statement in &block_data.statements {
+        match statement.kind {
+            StatementKind::Expr(ref stmt) => {
+                visit::Visitor::visit_stmt(&mut visitor, stmt);
+            }
+            StatementKind::Declare(_) |
+            StatementKind::Drop { .. } => {}
+            StatementKind::Let { ref rvalue, .. } => {
+                visitor.visit_rvalue(rvalue);
+            }
+            StatementKind::Assign(ref lvalue, ref rvalue) => {
+                visitor.visit_assigned_lvalue(lvalue);
+                visitor.visit_rvalue(rvalue);
+            }
+            StatementKind::Call { ref lvalue, ref fun, ref args } => {
+                visitor.visit_assigned_lvalue(lvalue);
+                visitor.visit_operand(fun);
+                for arg in args {
+                    visitor.visit_rvalue(arg);
+                }
+            }
+            StatementKind::MethodCall { ref lvalue, ref self_, ref args, .. } => {
+                visitor.visit_assigned_lvalue(lvalue);
+                visitor.visit_operand(self_);
+                for arg in args {
+                    visitor.visit_rvalue(arg);
+                }
+            }
+        }
+    }
+
+    match block_data.terminator().kind {
+        TerminatorKind::Goto { .. } |
+        TerminatorKind::Return |
+        TerminatorKind::Unreachable => {}
+        TerminatorKind::If { ref cond, .. } => {
+            visitor.visit_operand(cond);
+        }
+        TerminatorKind::Match { ref discr, ref targets } => {
+            visitor.visit_operand(discr);
+            for arm in targets {
+                if let Some(ref guard) = arm.guard {
+                    visit::Visitor::visit_expr(&mut visitor, guard);
+                }
+            }
+        }
+        TerminatorKind::Suspend { ref rvalue, .. } => {
+            visitor.visit_rvalue(rvalue);
+        }
+    }
+
+    visitor.names
+}
+
+struct NameVisitor<'a> {
+    mir: &'a Mir,
+    names: HashSet<ast::Name>,
+}
+
+impl<'a> NameVisitor<'a> {
+    /// Assigning to a local doesn't read it, but assigning through a projection reads the base.
+    fn visit_assigned_lvalue(&mut self, lvalue: &Lvalue) {
+        match *lvalue {
+            Lvalue::Local(_) => {}
+            _ => self.visit_lvalue(lvalue),
+        }
+    }
+
+    fn visit_lvalue(&mut self, lvalue: &Lvalue) {
+        match *lvalue {
+            Lvalue::Local(local) => {
+                self.names.insert(self.mir.local_decls[local].name.name);
+            }
+            Lvalue::Static(ref expr) => {
+                visit::Visitor::visit_expr(self, expr);
+            }
+            Lvalue::Projection(ref projection) => {
+                self.visit_lvalue(&projection.base);
+
+                if let ProjectionElem::Index(ref index) = projection.elem {
+                    self.visit_operand(index);
+                }
+            }
+        }
+    }
+
+    fn visit_operand(&mut self, operand: &Operand) {
+        if let Operand::Consume(ref lvalue) = *operand {
+            self.visit_lvalue(lvalue);
+        }
+    }
+
+    fn visit_rvalue(&mut self, rvalue: &Rvalue) {
+        match *rvalue {
+            Rvalue::Use(ref operand) |
+            Rvalue::UnaryOp(_, ref operand) |
+            Rvalue::Type(ref operand, _) => {
+                self.visit_operand(operand);
+            }
+            Rvalue::Mac(ref mac) => {
+                visit::Visitor::visit_mac(self, mac);
+            }
+            Rvalue::Ref(_, ref lvalue) => {
+                self.visit_lvalue(lvalue);
+            }
+            Rvalue::BinaryOp(_, ref lhs, ref rhs) => {
+                self.visit_operand(lhs);
+                self.visit_operand(rhs);
+            }
+            Rvalue::Tuple(ref operands) => {
+                for operand in operands {
+                    self.visit_operand(operand);
+                }
+            }
+            Rvalue::Struct(_, _, ref operands, ref wth) => {
+                for operand in operands.iter().chain(wth) {
+                    self.visit_operand(operand);
+                }
+            }
+            Rvalue::Range(ref start, ref end, _) => {
+                for operand in start.iter().chain(end) {
+                    self.visit_operand(operand);
+                }
+            }
+            Rvalue::Repeat(ref value, ref count) => {
+                self.visit_operand(value);
+                visit::Visitor::visit_expr(self, count);
+            }
+            Rvalue::Closure(ref expr) |
+            Rvalue::Recompute(ref expr) => {
+                visit::Visitor::visit_expr(self, expr);
+            }
+        }
+    }
+
+    fn visit_tts(&mut self, tts: &[TokenTree]) {
+        for tt in tts {
+            match *tt {
+                TokenTree::Token(_, Token::Ident(ident)) => {
+                    self.names.insert(ident.name);
+                }
+                TokenTree::Token(..) => {}
+                TokenTree::Delimited(_, ref delimited) => {
+                    self.visit_tts(&delimited.tts);
+                }
+                TokenTree::Sequence(_, ref sequence) => {
+                    self.visit_tts(&sequence.tts);
+                }
+            }
+        }
+    }
+}
+
+impl<'a> visit::Visitor for NameVisitor<'a> {
+    fn visit_ident(&mut self, _span: Span, ident: ast::Ident) {
+        self.names.insert(ident.name);
+    }
+
+    fn visit_mac(&mut self, mac: &ast::Mac) {
+        self.visit_tts(&mac.node.tts);
+    }
+}
@@ -8,9 +8,12 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use build::mac::is_mac;
 use build::{BlockAnd, BlockAndExtension, Builder};
 use mir::*;
-use syntax::ast::{self, StmtKind};
+use std::collections::HashSet;
+use syntax::ast::{self, ExprKind, StmtKind};
+use syntax::codemap::Span;
 use syntax::ptr::P;
 
 impl<'a, 'b: 'a> Builder<'a, 'b> {
@@ -39,6 +42,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             //
             // First we build all the statements in the block.
             let mut let_extent_stack = Vec::with_capacity(8);
+            let outer_diverging_fns = this.declare_diverging_fns(stmts);
             let outer_visibility_scope = this.visibility_scope;
             //this.visibility_scope = this.new_visibility_scope(ast_block.span);
 
@@ -47,7 +51,13 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                     StmtKind::Expr(ref expr) | StmtKind::Semi(ref expr) => {
                         let extent = this.start_new_extent();
                         unpack!(block = this.in_scope(extent, stmt.span, block, |this| {
-                            this.stmt_expr(block, expr)
+                            let block = unpack!(this.stmt_expr(block, expr));
+
+                            if this.is_diverging_call(expr) {
+                                this.diverge(stmt.span, block).unit()
+                            } else {
+                                block.unit()
+                            }
                         }));
                     }
                     StmtKind::Mac(ref mac) => {
@@ -66,7 +76,12 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                                     });
 
                                     this.push_assign_unit(stmt.span, block, &temp);
-                                    block.unit()
+
+                                    if is_diverging_mac(mac) {
+                                        this.diverge(stmt.span, block).unit()
+                                    } else {
+                                        block.unit()
+                                    }
                                 }
                             }
                         }));
@@ -97,8 +112,8 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                             this.visibility_scope = visibility_scope;
                         }
                     }
-                    StmtKind::Item(..) => {
-                        span_bug!(this.cx, stmt.span, "Cannot handle item declarations yet");
+                    StmtKind::Item(ref item) => {
+                        this.items.push(item.clone());
                     }
                 }
             }
@@ -114,11 +129,75 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             for extent in let_extent_stack.into_iter().rev() {
                 unpack!(block = this.pop_scope(extent, ast_block.span, block));
             }
-            // Restore the original visibility scope.
+            // Restore the original visibility scope, and forget the block's diverging functions.
             this.visibility_scope = outer_visibility_scope;
+            this.diverging_fns = outer_diverging_fns;
             block.unit()
         })
     }
+
+    /// Remember the functions declared in a block that return `!`, and forget the ones shadowed by
+    /// other functions in the block. Items are visible in the whole block, so this happens before
+    /// any of its statements are lowered. Returns the enclosing block's set, to restore once the
+    /// block ends.
+    fn declare_diverging_fns(&mut self, stmts: &[ast::Stmt]) -> HashSet<ast::Name> {
+        let outer_diverging_fns = self.diverging_fns.clone();
+
+        for stmt in stmts {
+            if let StmtKind::Item(ref item) = stmt.node {
+                if let ast::ItemKind::Fn(ref fn_decl, _, _, _, _, _) = item.node {
+                    let is_diverging = match fn_decl.output {
+                        ast::FunctionRetTy::Ty(ref ty) => {
+                            match ty.node {
+                                ast::TyKind::Never => true,
+                                _ => false,
+                            }
+                        }
+                        ast::FunctionRetTy::Default(_) => false,
+                    };
+
+                    if is_diverging {
+                        self.diverging_fns.insert(item.ident.name);
+                    } else {
+                        self.diverging_fns.remove(&item.ident.name);
+                    }
+                }
+            }
+        }
+
+        outer_diverging_fns
+    }
+
+    /// Whether `expr` calls a function declared in the body that returns `!`, and isn't shadowed by
+    /// a local binding like a closure.
+    fn is_diverging_call(&self, expr: &ast::Expr) -> bool {
+        match expr.node {
+            ExprKind::Call(ref fun, _) => {
+                match fun.node {
+                    ExprKind::Path(None, ref path) => {
+                        !path.global &&
+                            path.segments.len() == 1 &&
+                            self.diverging_fns.contains(&path.segments[0].identifier.name) &&
+                            self.get_local_from_path(path).is_none()
+                    }
+                    _ => false,
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// End `block` with an `Unreachable` terminator, and start a new block for any code that
+    /// follows the diverging statement.
+    fn diverge(&mut self, span: Span, block: BasicBlock) -> BasicBlock {
+        self.terminate(span, block, TerminatorKind::Unreachable);
+        self.start_new_block(span, Some("AfterDiverge"))
+    }
+}
+
+/// Whether a statement macro always panics.
+fn is_diverging_mac(mac: &ast::Mac) -> bool {
+    is_mac(mac, "panic") || is_mac(mac, "unreachable") || is_mac(mac, "unimplemented")
 }
 
 fn split_stmts(stmts: &[ast::Stmt]) -> (&[ast::Stmt], Option<P<ast::Expr>>) {
@@ -15,7 +15,18 @@ impl CFG {
                            span: Span,
                            name: Option<&'static str>,
                            live_decls: LiveDeclMap) -> BasicBlock {
-        self.basic_blocks.push(BasicBlockData::new(span, name, live_decls))
+        let block = self.basic_blocks.push(BasicBlockData::new(span, name, live_decls));
+        self.reachable.push(block == START_BLOCK);
+        lowering_trace!("start_new_block: block={:?} name={:?}", block, name);
+
+        block
+    }
+
+    /// Returns whether the block can be reached from the start block, only following the blocks
+    /// that have been terminated so far. Since we build the CFG in order, this tells us if the
+    /// code we're currently lowering comes after a `break`, `continue` or `return`.
+    pub fn is_reachable(&self, block: BasicBlock) -> bool {
+        self.reachable[block]
     }
 
     pub fn push(&mut self, block: BasicBlock, statement: Statement) {
@@ -29,10 +40,39 @@ impl CFG {
         assert!(self.block_data(block).terminator.is_none(),
                 "terminate: block {:?} already has a terminator set", block);
 
-        let block_data = self.block_data_mut(block);
-        block_data.terminator = Some(Terminator {
-            source_info: source_info,
-            kind: kind,
-        });
+        lowering_trace!("terminate: block={:?} kind={:?}", block, kind);
+
+        {
+            let block_data = self.block_data_mut(block);
+            block_data.terminator = Some(Terminator {
+                source_info: source_info,
+                kind: kind,
+            });
+        }
+
+        if self.reachable[block] {
+            self.mark_successors_reachable(block);
+        }
+    }
+
+    /// Mark everything that's now reachable through `block`'s terminator, including the blocks
+    /// that were terminated before we knew they were reachable. A block is only marked once, so
+    /// this is linear in the size of the CFG over the whole build.
+    fn mark_successors_reachable(&mut self, block: BasicBlock) {
+        let mut worklist = vec![block];
+
+        while let Some(bb) = worklist.pop() {
+            let successors = match self.basic_blocks[bb].terminator {
+                Some(ref terminator) => terminator.successors(),
+                None => { continue; }
+            };
+
+            for successor in successors {
+                if !self.reachable[successor] {
+                    self.reachable[successor] = true;
+                    worklist.push(successor);
+                }
+            }
+        }
     }
 }
@@ -1,45 +1,295 @@
 use aster::AstBuilder;
+use build::mac::{is_path, parse_mac, parse_mac_matches};
 use build::transition::{self, Transition};
 use mir::*;
+use options::Options;
 use syntax::ast::{self, ExprKind, StmtKind};
-use syntax::codemap::respan;
+use syntax::codemap::{DUMMY_SP, respan};
 use syntax::ext::base::ExtCtxt;
 use syntax::ext::tt::transcribe::new_tt_reader;
 use syntax::fold::{self, Folder};
 use syntax::parse::parser::Parser;
-use syntax::parse::token::Token;
+use syntax::parse::token::{Token, keywords};
 use syntax::ptr::P;
 use syntax::util::small_vector::SmallVector;
 
 pub fn desugar_block(cx: &ExtCtxt,
                      state_machine_kind: StateMachineKind,
+                     options: &Options,
                      fn_decl: FunctionDecl,
                      block: P<ast::Block>) -> (FunctionDecl, P<ast::Block>) {
     let mut desugar = Desugar {
         cx: cx,
         state_machine_kind: state_machine_kind,
+        yield_macro: options.yield_macro(),
+        item_ty: options.item.clone(),
+        has_context: options.context.is_some(),
+        reserve: options.reserve,
+        uses_weak: false,
     };
 
     let mut assigner = AssignIds {
         next_node_id: ast::NodeId::new(1),
     };
 
+    let fn_decl = desugar_impl_trait_args(fn_decl);
+    let fn_decl = desugar_self_arg(fn_decl);
+
+    let block = if fn_decl.self_arg.is_some() {
+        RenameSelf.fold_block(block)
+    } else {
+        block
+    };
+
     let fn_decl = FunctionDecl::new(
         assigner.fold_ident(desugar.fold_ident(fn_decl.ident)),
         assigner.fold_fn_decl(desugar.fold_fn_decl(fn_decl.fn_decl)),
         fn_decl.unsafety,
         fn_decl.abi,
         assigner.fold_generics(desugar.fold_generics(fn_decl.generics)),
-    );
+    ).with_self_arg(fn_decl.self_arg);
 
-    let block = assigner.fold_block(desugar.fold_block(block));
+    let block = desugar.fold_block(block);
+
+    let block = if desugar.uses_weak {
+        declare_downgrade(cx, block)
+    } else {
+        block
+    };
+
+    let block = assigner.fold_block(block);
 
     (fn_decl, block)
 }
 
+/// `impl Trait` isn't allowed in argument position, so give each of those arguments its own type
+/// parameter with the same bounds.
+fn desugar_impl_trait_args(fn_decl: FunctionDecl) -> FunctionDecl {
+    let FunctionDecl { ident, fn_decl, unsafety, abi, generics, self_arg } = fn_decl;
+
+    let mut ty_params = vec![];
+
+    let fn_decl = fn_decl.map(|mut fn_decl| {
+        for (index, arg) in fn_decl.inputs.iter_mut().enumerate() {
+            let bounds = match arg.ty.node {
+                ast::TyKind::ImplTrait(ref bounds) => bounds.clone(),
+                _ => { continue; }
+            };
+
+            let ast_builder = AstBuilder::new().span(arg.ty.span);
+            let ty_param_id = ast_builder.id(format!("__StatefulImplTrait{}", index));
+
+            ty_params.push(
+                ast_builder.ty_param(ty_param_id)
+                    .with_bounds(bounds.iter().cloned())
+                    .build()
+            );
+
+            arg.ty = ast_builder.ty().id(ty_param_id);
+        }
+
+        fn_decl
+    });
+
+    let generics = AstBuilder::new().generics()
+        .with(generics)
+        .with_ty_params(ty_params)
+        .build();
+
+    FunctionDecl::new(ident, fn_decl, unsafety, abi, generics).with_self_arg(self_arg)
+}
+
+/// `self` can't be bound by a state's pattern, so bind a method's `self` argument as
+/// `__stateful_self`, and keep the original argument for the signature. A `mut self` is bound
+/// mutably, so the body can still assign to its fields.
+///
+/// A boxed trait object defaults to `'static`, so if the method borrows `self` and returns one,
+/// bound it by the borrow instead, naming the borrow's lifetime if it was elided. This lets the
+/// state machine hold on to `self`.
+fn desugar_self_arg(fn_decl: FunctionDecl) -> FunctionDecl {
+    if !fn_decl.fn_decl.has_self() {
+        return fn_decl;
+    }
+
+    let FunctionDecl { ident, fn_decl, unsafety, abi, mut generics, .. } = fn_decl;
+
+    let mut self_arg = fn_decl.inputs[0].clone();
+    let ast_builder = AstBuilder::new().span(self_arg.ty.span);
+
+    let fn_decl = fn_decl.map(|mut fn_decl| {
+        let output = match fn_decl.output {
+            ast::FunctionRetTy::Ty(ref ty) if is_unbounded_boxed_trait_object(ty) => {
+                let self_ty = self_arg.ty.clone();
+
+                match self_ty.node {
+                    ast::TyKind::Rptr(lifetime, ref mut_ty) => {
+                        let lifetime = match lifetime {
+                            Some(lifetime) => lifetime,
+                            None => {
+                                generics = ast_builder.generics()
+                                    .with(generics.clone())
+                                    .lifetime_name("'__stateful_self")
+                                    .build();
+
+                                let lifetime = ast_builder.lifetime("'__stateful_self");
+
+                                self_arg.ty = ast_builder.ty()
+                                    .build_ty_kind(ast::TyKind::Rptr(Some(lifetime),
+                                                                     mut_ty.clone()));
+
+                                lifetime
+                            }
+                        };
+
+                        Some(ast::FunctionRetTy::Ty(bound_boxed_trait_object(ty, lifetime)))
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+
+        if let Some(output) = output {
+            fn_decl.output = output;
+        }
+
+        let self_pat = match self_arg.pat.node {
+            ast::PatKind::Ident(ast::BindingMode::ByValue(ast::Mutability::Mutable), _, _) => {
+                ast_builder.pat().mut_id(SELF_IDENT)
+            }
+            _ => ast_builder.pat().id(SELF_IDENT),
+        };
+
+        fn_decl.inputs[0] = ast::Arg {
+            ty: explicit_self_ty(&self_arg.ty),
+            pat: self_pat,
+            id: ast::DUMMY_NODE_ID,
+        };
+
+        fn_decl
+    });
+
+    FunctionDecl::new(ident, fn_decl, unsafety, abi, generics).with_self_arg(Some(self_arg))
+}
+
+/// Replace the implicit `Self` in a `self` argument's type, like in `&self`, with `Self`.
+fn explicit_self_ty(ty: &P<ast::Ty>) -> P<ast::Ty> {
+    let ast_builder = AstBuilder::new().span(ty.span);
+
+    match ty.node {
+        ast::TyKind::ImplicitSelf => ast_builder.ty().id("Self"),
+        ast::TyKind::Rptr(lifetime, ref mut_ty) => {
+            let mut_ty = ast::MutTy {
+                ty: explicit_self_ty(&mut_ty.ty),
+                mutbl: mut_ty.mutbl,
+            };
+
+            ast_builder.ty().build_ty_kind(ast::TyKind::Rptr(lifetime, mut_ty))
+        }
+        _ => ty.clone(),
+    }
+}
+
+/// Returns whether the type is a `Box<Trait>` without a lifetime bound.
+fn is_unbounded_boxed_trait_object(ty: &ast::Ty) -> bool {
+    match boxed_ty(ty) {
+        Some(boxed_ty) => {
+            match boxed_ty.node {
+                ast::TyKind::Path(None, _) => true,
+                ast::TyKind::ObjectSum(_, ref bounds) => {
+                    !bounds.iter().any(|bound| {
+                        match *bound {
+                            ast::TyParamBound::RegionTyParamBound(_) => true,
+                            ast::TyParamBound::TraitTyParamBound(..) => false,
+                        }
+                    })
+                }
+                _ => false,
+            }
+        }
+        None => false,
+    }
+}
+
+/// Turn `Box<Trait + Bounds>` into `Box<Trait + Bounds + $lifetime>`.
+fn bound_boxed_trait_object(ty: &ast::Ty, lifetime: ast::Lifetime) -> P<ast::Ty> {
+    let ast_builder = AstBuilder::new().span(ty.span);
+    let boxed_ty = boxed_ty(ty).expect("boxed trait object");
+
+    let (trait_ty, bounds) = match boxed_ty.node {
+        ast::TyKind::ObjectSum(ref trait_ty, ref bounds) => (trait_ty.clone(), bounds.to_vec()),
+        _ => (boxed_ty.clone(), vec![]),
+    };
+
+    ast_builder.ty().box_()
+        .object_sum().build(trait_ty)
+            .with_bounds(bounds)
+            .with_bound(ast::TyParamBound::RegionTyParamBound(lifetime))
+            .build()
+}
+
+fn boxed_ty(ty: &ast::Ty) -> Option<&P<ast::Ty>> {
+    let segment = match ty.node {
+        ast::TyKind::Path(None, ref path) => {
+            match path.segments.last() {
+                Some(segment) => segment,
+                None => { return None; }
+            }
+        }
+        _ => { return None; }
+    };
+
+    if &*segment.identifier.name.as_str() != "Box" {
+        return None;
+    }
+
+    match segment.parameters {
+        ast::PathParameters::AngleBracketed(ref data) if data.types.len() == 1 => {
+            data.types.first()
+        }
+        _ => None,
+    }
+}
+
+/// Rename uses of `self` in a method's body to the name it's bound to in the state machine.
+struct RenameSelf;
+
+impl fold::Folder for RenameSelf {
+    fn fold_ident(&mut self, ident: ast::Ident) -> ast::Ident {
+        if ident.name == keywords::SelfValue.name() {
+            AstBuilder::new().id(SELF_IDENT)
+        } else {
+            ident
+        }
+    }
+
+    fn fold_path(&mut self, path: ast::Path) -> ast::Path {
+        // Leave module paths like `self::foo` alone.
+        if path.segments.len() > 1 {
+            path
+        } else {
+            fold::noop_fold_path(path, self)
+        }
+    }
+
+    fn fold_item(&mut self, item: P<ast::Item>) -> SmallVector<P<ast::Item>> {
+        // Nested items can't refer to the method's `self`.
+        SmallVector::one(item)
+    }
+
+    fn fold_mac(&mut self, mac: ast::Mac) -> ast::Mac {
+        fold::noop_fold_mac(mac, self)
+    }
+}
+
 struct Desugar<'a, 'b: 'a> {
     cx: &'a ExtCtxt<'b>,
     state_machine_kind: StateMachineKind,
+    yield_macro: &'a str,
+    item_ty: Option<P<ast::Ty>>,
+    has_context: bool,
+    reserve: Option<usize>,
+    uses_weak: bool,
 }
 
 impl<'a, 'b> Desugar<'a, 'b> {
@@ -50,10 +300,16 @@ impl<'a, 'b> Desugar<'a, 'b> {
     pub fn expr_mac(&mut self, mac: &ast::Mac) -> Option<P<ast::Expr>> {
         // NOTE: we're folding then desugaring because `suspend!()` cannot currently be directly
         // called.
-        match (self.state_machine_kind, transition::parse_mac_transition(self.cx, mac)) {
+        let transition = transition::parse_mac_transition(self.cx, self.yield_macro, mac);
+
+        match (self.state_machine_kind, transition) {
             (StateMachineKind::Generator, Some(transition::Transition::Yield(expr))) => {
                 let expr = self.fold_sub_expr(expr);
-                Some(desugar_yield(self.cx, expr))
+                Some(desugar_yield(self.cx, self.item_ty.as_ref(), expr))
+            }
+            (StateMachineKind::Generator, Some(transition::Transition::YieldFrom(expr))) => {
+                // The loop we desugar into still needs to be desugared itself.
+                Some(self.fold_expr(desugar_yield_from(self.cx, self.item_ty.as_ref(), expr)))
             }
             (StateMachineKind::Async, Some(transition::Transition::Await(expr))) => {
                 let expr = self.fold_sub_expr(expr);
@@ -63,7 +319,29 @@ impl<'a, 'b> Desugar<'a, 'b> {
                 if is_try_path(&mac.node.path) {
                     let expr = parse_mac_try(self.cx, mac);
                     let expr = self.fold_sub_expr(expr);
-                    Some(desugar_try(expr))
+                    Some(desugar_try(self.cx,
+                                     self.state_machine_kind,
+                                     self.item_ty.as_ref(),
+                                     expr))
+                } else if is_path(&mac.node.path, "matches") {
+                    let (expr, pats, guard) = parse_mac_matches(self.cx, mac);
+                    let expr = self.fold_sub_expr(expr);
+                    Some(desugar_matches(expr, pats, guard))
+                } else if is_path(&mac.node.path, "weak") {
+                    let expr = parse_mac(self.cx, mac);
+                    let expr = self.fold_sub_expr(expr);
+                    self.uses_weak = true;
+                    Some(desugar_weak(self.cx, expr))
+                } else if is_path(&mac.node.path, "ctx") {
+                    if !self.has_context {
+                        self.cx.span_err(
+                            mac.span,
+                            &format!("`ctx!()` requires a context, as in \
+                                      `#[{}(context = \"Type\")]`",
+                                     self.state_machine_kind));
+                    }
+
+                    Some(desugar_ctx(self.cx))
                 } else {
                     None
                 }
@@ -77,17 +355,33 @@ impl<'a, 'b: 'a> fold::Folder for Desugar<'a, 'b> {
         expr.map(|expr| {
             match expr.node {
                 ExprKind::ForLoop(pat, expr, loop_block, label) => {
-                    desugar_for_loop(
-                        self.fold_pat(pat),
-                        self.fold_expr(expr),
-                        self.fold_block(loop_block),
-                        label.map(|label| {
-                            respan(
-                                self.new_span(label.span),
-                                self.fold_ident(label.node)
-                            )
-                        })
-                    ).unwrap()
+                    let pat = self.fold_pat(pat);
+                    let expr = self.fold_expr(expr);
+                    let loop_block = self.fold_block(loop_block);
+                    let label = label.map(|label| {
+                        respan(
+                            self.new_span(label.span),
+                            self.fold_ident(label.node)
+                        )
+                    });
+
+                    let range = match expr.node {
+                        ExprKind::Range(Some(ref start), Some(ref end), limits)
+                            if is_int_lit(start) && is_int_lit(end) => {
+                            Some((start.clone(), end.clone(), limits))
+                        }
+                        _ => None,
+                    };
+
+                    match range {
+                        Some((start, end, limits)) => {
+                            desugar_range_for_loop(
+                                self.cx, pat, start, end, limits, loop_block, label)
+                        }
+                        None => {
+                            desugar_for_loop(pat, expr, loop_block, label)
+                        }
+                    }.unwrap()
                 }
                 ExprKind::IfLet(pat, expr, then_block, else_block) => {
                     let expr = desugar_if_let(pat, expr, then_block, else_block).unwrap();
@@ -97,17 +391,9 @@ impl<'a, 'b: 'a> fold::Folder for Desugar<'a, 'b> {
                     let expr = desugar_while_let(pat, expr, then_block, label).unwrap();
                     fold::noop_fold_expr(expr, self)
                 }
-                ExprKind::While(cond, body, opt_ident) => {
-                    let body = self.fold_block(body);
-                    let opt_ident = opt_ident.map(|label| {
-                        respan(self.new_span(label.span), self.fold_ident(label.node))
-                    });
-
-                    let node = ExprKind::While(self.fold_expr(cond),
-                                        self.fold_block(body),
-                                        opt_ident.map(|label| respan(self.new_span(label.span),
-                                                                    self.fold_ident(label.node))));
-                    ast::Expr { node: node, .. expr }
+                ExprKind::While(cond, body, label) => {
+                    let expr = desugar_while(self.cx, cond, body, label).unwrap();
+                    fold::noop_fold_expr(expr, self)
                 }
                 ExprKind::Loop(body, opt_ident) => {
                     let body = self.fold_block(body);
@@ -117,6 +403,16 @@ impl<'a, 'b: 'a> fold::Folder for Desugar<'a, 'b> {
                     let node = ExprKind::Loop(body, opt_ident);
                     ast::Expr { node: node, .. expr }
                 }
+                node @ ExprKind::Closure(..) => {
+                    // Closures are passed through untouched, so their bodies keep their own
+                    // loops and `?`.
+                    ast::Expr { node: node, .. expr }
+                }
+                ExprKind::Try(expr) => {
+                    let expr = self.fold_expr(expr);
+                    let item_ty = self.item_ty.as_ref();
+                    desugar_try(self.cx, self.state_machine_kind, item_ty, expr).unwrap()
+                }
                 ExprKind::Mac(mac) => {
                     if let Some(expr) = self.expr_mac(&mac) {
                         expr.unwrap()
@@ -150,14 +446,27 @@ impl<'a, 'b: 'a> fold::Folder for Desugar<'a, 'b> {
             _ => {}
         }
 
+        let stmt = match self.reserve {
+            Some(reserve) => reserve_accumulator(self.cx, stmt, reserve),
+            None => stmt,
+        };
+
         fold::noop_fold_stmt(stmt, self)
     }
 
+    fn fold_item(&mut self, item: P<ast::Item>) -> SmallVector<P<ast::Item>> {
+        // Items are hoisted as-is into the generated function. Any transitions inside of them
+        // belong to a nested state machine, which gets expanded on its own.
+        SmallVector::one(item)
+    }
+
     fn fold_mac(&mut self, mac: ast::Mac) -> ast::Mac {
         // this macro may contain transitions. So parse it, expand the inner expression,
         // then convert it back into a macro.
-        match transition::parse_mac_transition(self.cx, &mac) {
-            Some(Transition::Yield(expr)) | Some(Transition::Await(expr)) => {
+        match transition::parse_mac_transition(self.cx, self.yield_macro, &mac) {
+            Some(Transition::Yield(expr)) |
+            Some(Transition::YieldFrom(expr)) |
+            Some(Transition::Await(expr)) => {
                 let expr = self.fold_expr(expr);
                 AstBuilder::new().span(mac.span).mac()
                     .build_path(mac.node.path.clone())
@@ -214,11 +523,13 @@ fn is_try_path(path: &ast::Path) -> bool {
 ///     'label: loop {
 ///         match iter.next() {
 ///             ::std::option::Option::Some($pat) => $body,
-///             ::std::option::Option::None => break,
+///             ::std::option::Option::None => break 'label,
 ///         }
 ///     }
 /// }
 /// ```
+///
+/// If the loop has no label, an internal one is used instead.
 fn desugar_for_loop(pat: P<ast::Pat>,
                     iter: P<ast::Expr>,
                     body: P<ast::Block>,
@@ -265,10 +576,14 @@ fn desugar_for_loop(pat: P<ast::Pat>,
     let none_pat = builder.pat().path()
         .global().ids(&["std", "option", "Option", "None"]).build();
 
-    // $none_pat => break,
+    // The exit `break` always targets this loop's label, so it can never be confused with a
+    // `break` or `continue` written by the user inside `$body`.
+    let label = loop_label(label);
+
+    // $none_pat => break 'label,
     let none_arm = builder.arm()
         .with_pat(none_pat)
-        .body().break_();
+        .body().break_to(label.node);
 
     // match $iter_next() {
     //     Some($pat) => $block,
@@ -280,16 +595,13 @@ fn desugar_for_loop(pat: P<ast::Pat>,
         .with_arm(none_arm)
         .build();
 
-    // `loop { $match_expr; };`
-    let mut loop_builder = builder.expr().loop_();
-
-    if let Some(label) = label {
-        loop_builder = loop_builder.label(label.node);
-    }
-
-    let loop_ = loop_builder.block()
+    // `'label: loop { $match_expr; };`
+    let loop_ = builder.expr().loop_()
+        .label(label.node)
+        .block()
         .stmt().build_expr(match_expr)
         .build();
+    let loop_ = mark_desugared_loop(loop_, DESUGARED_FOR);
 
     // `let mut iter = $into_iter;`
     let iter = builder.stmt()
@@ -306,6 +618,96 @@ fn desugar_for_loop(pat: P<ast::Pat>,
         .build()
 }
 
+/// Desugar a for loop over a range with integer literal bounds into a counter, rather than going
+/// through `IntoIterator`, so the state machine only carries the counter instead of a `Range`:
+///
+/// ```
+/// {
+///     let mut index = $start;
+///     'label: loop {
+///         if index >= $end { break 'label; }
+///         let $pat = index;
+///         index += 1;
+///         $body
+///     }
+/// }
+/// ```
+///
+/// Inclusive ranges instead track if the last value was produced, so `$end` can be the maximum
+/// value of its type without overflowing the counter. Ranges over anything else, like other
+/// `Step` types or bounds computed at runtime, go through `IntoIterator` as usual.
+fn desugar_range_for_loop(cx: &ExtCtxt,
+                          pat: P<ast::Pat>,
+                          start: P<ast::Expr>,
+                          end: P<ast::Expr>,
+                          limits: ast::RangeLimits,
+                          body: P<ast::Block>,
+                          label: Option<ast::SpannedIdent>) -> P<ast::Expr> {
+    let builder = AstBuilder::new().span(start.span);
+
+    let label = loop_label(label);
+    let break_ = builder.expr().break_to(label.node);
+
+    let (done, exit_cond, advance) = match limits {
+        ast::RangeLimits::HalfOpen => {
+            let exit_cond = quote_expr!(cx, copied!(__stateful_index) >= $end);
+
+            let advance = quote_stmt!(cx, __stateful_index += 1;).unwrap();
+
+            (None, exit_cond, advance)
+        }
+        ast::RangeLimits::Closed => {
+            let done = quote_stmt!(cx,
+                let mut __stateful_done = copied!(__stateful_index) > $end;
+            ).unwrap();
+
+            let exit_cond = quote_expr!(cx, copied!(__stateful_done));
+
+            let advance = quote_stmt!(cx,
+                if copied!(__stateful_index) == $end {
+                    __stateful_done = true;
+                } else {
+                    __stateful_index += 1;
+                }
+            ).unwrap();
+
+            (Some(done), exit_cond, advance)
+        }
+    };
+
+    // `'label: loop { ... }`
+    let loop_ = builder.expr().loop_()
+        .label(label.node)
+        .block()
+        .stmt().build_expr(quote_expr!(cx, if $exit_cond { $break_; }))
+        .with_stmt(quote_stmt!(cx, let $pat = copied!(__stateful_index);).unwrap())
+        .with_stmt(advance)
+        .stmt().build_expr(builder.expr().build_expr_kind(ExprKind::Block(body)))
+        .build();
+    let loop_ = mark_desugared_loop(loop_, DESUGARED_FOR);
+
+    builder.expr().block()
+        .with_stmt(quote_stmt!(cx, let mut __stateful_index = $start;).unwrap())
+        .with_stmts(done)
+        .stmt().build_expr(loop_)
+        .build()
+}
+
+/// Whether the expression is an integer literal, possibly negated. Ranges can be over any `Step`
+/// type, so these are the only bounds we know the counter can be advanced with `+= 1`.
+fn is_int_lit(expr: &ast::Expr) -> bool {
+    match expr.node {
+        ExprKind::Lit(ref lit) => {
+            match lit.node {
+                ast::LitKind::Int(..) => true,
+                _ => false,
+            }
+        }
+        ExprKind::Unary(ast::UnOp::Neg, ref expr) => is_int_lit(expr),
+        _ => false,
+    }
+}
+
 /// Desugar an if-let:
 ///
 /// ```rust
@@ -349,10 +751,12 @@ fn desugar_if_let(pat: P<ast::Pat>,
 /// 'label: loop {
 ///     match $expr {
 ///         $pat => $body_block,
-///         _ => break,
+///         _ => break 'label,
 ///     }
 /// }
 /// ```
+///
+/// If the loop has no label, an internal one is used instead.
 fn desugar_while_let(pat: P<ast::Pat>,
                      expr: P<ast::Expr>,
                      then_block: P<ast::Block>,
@@ -364,10 +768,12 @@ fn desugar_while_let(pat: P<ast::Pat>,
         .with_pat(pat.clone())
         .body().build_block(then_block.clone());
 
-    // _ => break
+    let label = loop_label(label);
+
+    // _ => break 'label
     let else_arm = builder.arm()
         .pat().wild()
-        .body().break_();
+        .body().break_to(label.node);
 
     // match $expr {
     //     $then_arm,
@@ -380,15 +786,78 @@ fn desugar_while_let(pat: P<ast::Pat>,
         .build();
 
     // `'$label: loop { $match_expr; };`
-    let mut loop_builder = builder.expr().loop_();
+    let loop_ = builder.expr().loop_()
+        .label(label.node)
+        .block()
+        .stmt().build_expr(match_expr)
+        .build();
 
-    if let Some(label) = label {
-        loop_builder = loop_builder.label(label.node);
-    }
+    mark_desugared_loop(loop_, DESUGARED_WHILE_LET)
+}
 
-    loop_builder.block()
-        .stmt().build_expr(match_expr)
-        .build()
+/// Desugar a while loop:
+///
+/// ```rust
+/// 'label: loop {
+///     if !$cond { break 'label; }
+///     $body
+/// }
+/// ```
+///
+/// If the loop has no label, an internal one is used instead. Since the condition is checked at
+/// the top of the loop, `continue` still re-evaluates it. That includes a `continue` inside the
+/// condition itself, which starts evaluating the condition over again.
+fn desugar_while(cx: &ExtCtxt,
+                 cond: P<ast::Expr>,
+                 body: P<ast::Block>,
+                 label: Option<ast::SpannedIdent>) -> P<ast::Expr> {
+    let builder = AstBuilder::new().span(cond.span);
+
+    let label = loop_label(label);
+    let break_ = builder.expr().break_to(label.node);
+
+    // `'label: loop { if !$cond { break 'label; } $body }`
+    let loop_ = builder.expr().loop_()
+        .label(label.node)
+        .block()
+        .stmt().build_expr(quote_expr!(cx, if !$cond { $break_; }))
+        .stmt().build_expr(builder.expr().build_expr_kind(ExprKind::Block(body)))
+        .build();
+
+    mark_desugared_loop(loop_, DESUGARED_WHILE)
+}
+
+const DESUGARED_FOR: (&'static str, &'static str) = ("stateful_desugared_for", "for");
+const DESUGARED_WHILE: (&'static str, &'static str) = ("stateful_desugared_while", "while");
+const DESUGARED_WHILE_LET: (&'static str, &'static str) =
+    ("stateful_desugared_while_let", "while let");
+
+/// Mark a `loop` with the kind of loop it was desugared from, since only a `loop` written by the
+/// user can be broken out of with a value.
+fn mark_desugared_loop(loop_: P<ast::Expr>,
+                       (attr_name, _): (&'static str, &'static str)) -> P<ast::Expr> {
+    loop_.map(|expr| {
+        ast::Expr {
+            attrs: vec![AstBuilder::new().attr().word(attr_name)].into(),
+            .. expr
+        }
+    })
+}
+
+/// Returns the kind of loop, like `for`, that this `loop` was desugared from.
+pub fn desugared_loop_kind(expr: &ast::Expr) -> Option<&'static str> {
+    [DESUGARED_FOR, DESUGARED_WHILE, DESUGARED_WHILE_LET].iter()
+        .find(|&&(attr_name, _)| expr.attrs.iter().any(|attr| attr.check_name(attr_name)))
+        .map(|&(_, kind)| kind)
+}
+
+/// Returns the user's loop label, or an internal label if the loop was unlabeled. Since loop
+/// scopes are searched from the innermost outwards, nested loops can safely share the internal
+/// label.
+fn loop_label(label: Option<ast::SpannedIdent>) -> ast::SpannedIdent {
+    label.unwrap_or_else(|| {
+        respan(DUMMY_SP, AstBuilder::new().id("'__stateful_loop"))
+    })
 }
 
 fn parse_mac_try(cx: &ExtCtxt, mac: &ast::Mac) -> P<ast::Expr> {
@@ -407,7 +876,7 @@ fn parse_mac_try(cx: &ExtCtxt, mac: &ast::Mac) -> P<ast::Expr> {
     expr
 }
 
-/// Desugar a `try!(...)`:
+/// Desugar a `try!(...)` or `$expr?`:
 ///
 /// ```
 /// match $expr {
@@ -416,8 +885,43 @@ fn parse_mac_try(cx: &ExtCtxt, mac: &ast::Mac) -> P<ast::Expr> {
 ///         return ::std::result::Result::Err(::std::convert::From::from(err)),
 /// }
 /// ```
-fn desugar_try(expr: P<ast::Expr>) -> P<ast::Expr> {
-    AstBuilder::new().span(expr.span).expr().try().build(expr)
+///
+/// A generator can't return a value, so instead it yields the error and then finishes:
+///
+/// ```
+/// match $expr {
+///     ::std::result::Result::Ok(value) => value,
+///     ::std::result::Result::Err(err) => {
+///         yield_!(::std::result::Result::Err(::std::convert::From::from(err)));
+///         return;
+///     }
+/// }
+/// ```
+///
+/// Since the scrutinee is evaluated first, a `yield_!($expr?)` only suspends with the `Ok` value.
+fn desugar_try(cx: &ExtCtxt,
+               state_machine_kind: StateMachineKind,
+               item_ty: Option<&P<ast::Ty>>,
+               expr: P<ast::Expr>) -> P<ast::Expr> {
+    match state_machine_kind {
+        StateMachineKind::Generator => {
+            let yield_err = desugar_yield(cx, item_ty, quote_expr!(cx,
+                ::std::result::Result::Err(::std::convert::From::from(moved!(err)))));
+
+            quote_expr!(cx,
+                match $expr {
+                    ::std::result::Result::Ok(value) => moved!(value),
+                    ::std::result::Result::Err(err) => {
+                        $yield_err;
+                        return;
+                    }
+                }
+            )
+        }
+        StateMachineKind::Async => {
+            AstBuilder::new().span(expr.span).expr().try().build(expr)
+        }
+    }
 }
 
 /// Compile `yield_!($expr)` into:
@@ -433,10 +937,183 @@ fn desugar_try(expr: P<ast::Expr>) -> P<ast::Expr> {
 ///     ...
 /// }
 /// ```
-fn desugar_yield(cx: &ExtCtxt, expr: P<ast::Expr>) -> P<ast::Expr> {
+///
+/// If the `item` option is set, the yielded value is first coerced to it with `coerce_to_item`.
+fn desugar_yield(cx: &ExtCtxt,
+                 item_ty: Option<&P<ast::Ty>>,
+                 expr: P<ast::Expr>) -> P<ast::Expr> {
+    let expr = match item_ty {
+        Some(ty) => coerce_to_item(cx, ty.clone(), expr),
+        None => expr,
+    };
+
     quote_expr!(cx, suspend!(Some($expr)))
 }
 
+/// Compile `$expr` into `{ let __stateful_item: $ty = $expr; __stateful_item }`, so a yielded
+/// value that doesn't coerce to the `item` type is reported at the yield.
+fn coerce_to_item(cx: &ExtCtxt, ty: P<ast::Ty>, expr: P<ast::Expr>) -> P<ast::Expr> {
+    quote_expr!(cx, {
+        let __stateful_item: $ty = $expr;
+        __stateful_item
+    })
+}
+
+/// Desugar `matches!($expr, $pats if $guard)`, so a scrutinee that suspends is lowered like any
+/// other `match`:
+///
+/// ```
+/// match $expr {
+///     $pats if $guard => true,
+///     _ => false,
+/// }
+/// ```
+fn desugar_matches(expr: P<ast::Expr>,
+                   pats: Vec<P<ast::Pat>>,
+                   guard: Option<P<ast::Expr>>) -> P<ast::Expr> {
+    let builder = AstBuilder::new().span(expr.span);
+
+    let match_arm = builder.arm()
+        .with_pats(pats)
+        .with_guard(guard)
+        .body().true_();
+
+    let else_arm = builder.arm().pat().wild().body().false_();
+
+    builder.expr()
+        .match_().build(expr)
+        .with_arm(match_arm)
+        .with_arm(else_arm)
+        .build()
+}
+
+/// Desugar `weak!($expr)`, which turns a strong `Rc` or `Arc` into a `Weak` so it can be held
+/// across a suspension point without keeping the value alive. The strong reference that's passed
+/// in is dropped right away, so the value has to be kept alive by another one:
+///
+/// ```
+/// {
+///     let strong = $expr;
+///     __StatefulDowngrade::__stateful_downgrade(&strong)
+/// }
+/// ```
+fn desugar_weak(cx: &ExtCtxt, expr: P<ast::Expr>) -> P<ast::Expr> {
+    quote_expr!(cx, {
+        let __stateful_strong = $expr;
+        __StatefulDowngrade::__stateful_downgrade(&__stateful_strong)
+    })
+}
+
+/// Declare the trait `weak!` downgrades through at the start of the body, so it works with both
+/// `Rc` and `Arc`. Items are hoisted into the generated function, so it's only declared once.
+fn declare_downgrade(cx: &ExtCtxt, block: P<ast::Block>) -> P<ast::Block> {
+    let downgrade = quote_block!(cx, {
+        trait __StatefulDowngrade {
+            type Weak;
+            fn __stateful_downgrade(&self) -> Self::Weak;
+        }
+
+        impl<T: ?Sized> __StatefulDowngrade for ::std::rc::Rc<T> {
+            type Weak = ::std::rc::Weak<T>;
+            fn __stateful_downgrade(&self) -> Self::Weak {
+                ::std::rc::Rc::downgrade(self)
+            }
+        }
+
+        impl<T: ?Sized> __StatefulDowngrade for ::std::sync::Arc<T> {
+            type Weak = ::std::sync::Weak<T>;
+            fn __stateful_downgrade(&self) -> Self::Weak {
+                ::std::sync::Arc::downgrade(self)
+            }
+        }
+    }).unwrap();
+
+    block.map(|block| {
+        let mut stmts = downgrade.stmts;
+        stmts.extend(block.stmts);
+        ast::Block { stmts: stmts, .. block }
+    })
+}
+
+/// Create the accumulator declared by `stmt` with room for `reserve` values up front, if it's
+/// declared with `Vec::new()` or `String::new()`.
+fn reserve_accumulator(cx: &ExtCtxt, stmt: ast::Stmt, reserve: usize) -> ast::Stmt {
+    let local = match stmt.node {
+        StmtKind::Local(ref local) => {
+            local.init.as_ref()
+                .and_then(|init| accumulator_new(init))
+                .map(|ty| {
+                    let init = desugar_reserve(cx, ty, reserve);
+                    local.clone().map(|local| ast::Local { init: Some(init), .. local })
+                })
+        }
+        _ => None,
+    };
+
+    match local {
+        Some(local) => ast::Stmt { node: StmtKind::Local(local), .. stmt },
+        None => stmt,
+    }
+}
+
+/// The accumulator type, if `expr` creates an empty `Vec` or `String` with `new()`.
+fn accumulator_new(expr: &ast::Expr) -> Option<&'static str> {
+    let path = match expr.node {
+        ExprKind::Call(ref func, ref args) if args.is_empty() => {
+            match func.node {
+                ExprKind::Path(None, ref path) => path,
+                _ => { return None; }
+            }
+        }
+        _ => { return None; }
+    };
+
+    ["Vec", "String"].iter()
+        .find(|name| {
+            !path.global && path.segments == AstBuilder::new()
+                .path().id(name).id("new")
+                .build().segments
+        })
+        .map(|name| *name)
+}
+
+/// Compile the `new()` call that creates an accumulator into:
+///
+/// ```
+/// $ty::with_capacity($reserve)
+/// ```
+fn desugar_reserve(cx: &ExtCtxt, ty: &str, reserve: usize) -> P<ast::Expr> {
+    let ty = AstBuilder::new().id(ty);
+    quote_expr!(cx, $ty::with_capacity($reserve))
+}
+
+/// Compile `ctx!()` into a borrow of the context held by the step function.
+fn desugar_ctx(cx: &ExtCtxt) -> P<ast::Expr> {
+    let context = AstBuilder::new().id(CONTEXT_IDENT);
+    quote_expr!(cx, &$context)
+}
+
+/// Compile `yield_from!($expr)` into:
+///
+/// ```
+/// for value in $expr {
+///     suspend!(Some(value));
+/// }
+/// ```
+///
+/// The yield is desugared directly, since the yield macro may have been renamed.
+fn desugar_yield_from(cx: &ExtCtxt,
+                      item_ty: Option<&P<ast::Ty>>,
+                      expr: P<ast::Expr>) -> P<ast::Expr> {
+    let yield_value = desugar_yield(cx, item_ty, quote_expr!(cx, __stateful_value));
+
+    quote_expr!(cx,
+        for __stateful_value in $expr {
+            $yield_value;
+        }
+    )
+}
+
 /// Compile `$result = await!($expr)` into:
 ///
 /// ```
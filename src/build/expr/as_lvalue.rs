@@ -29,55 +29,29 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                 this.in_scope(extent, block, |this| this.as_lvalue(block, value))
             }
             */
-            ExprKind::Field(ref _lhs, ref _name) => {
-                panic!("not supported yet: {:?}", expr)
-                /*
+            ExprKind::Field(ref lhs, name) => {
+                // If the base isn't an lvalue, this evaluates it into a temporary first, which
+                // may suspend if the base yields.
                 let lvalue = unpack!(block = this.as_lvalue(block, lhs));
-                let lvalue = lvalue.field(name, expr.ty);
+                let lvalue = lvalue.field(name);
                 block.and(lvalue)
-                */
             }
-            ExprKind::TupField(ref _lhs, ref _index) => {
-                panic!("not supported yet: {:?}", expr)
-
-                /*
+            ExprKind::TupField(ref lhs, index) => {
                 let lvalue = unpack!(block = this.as_lvalue(block, lhs));
-                let lvalue = lvalue.field(name, expr.ty);
+                let lvalue = lvalue.tuple_field(index);
                 block.and(lvalue)
-                */
             }
             ExprKind::Unary(ast::UnOp::Deref, ref arg) => {
                 let lvalue = unpack!(block = this.as_lvalue(block, arg));
                 let lvalue = lvalue.deref();
                 block.and(lvalue)
             }
-            ExprKind::Index(ref _lhs, ref _index) => {
-                panic!("not supported yet: {:?}", expr)
-
-                /*
-                let (usize_ty, bool_ty) = (this.hir.usize_ty(), this.hir.bool_ty());
-
+            ExprKind::Index(ref lhs, ref index) => {
+                // Evaluate left-to-right, so a yielding index suspends after the base has been
+                // evaluated. Bounds checking is left to the generated indexing expression.
                 let slice = unpack!(block = this.as_lvalue(block, lhs));
-
                 let idx = unpack!(block = this.as_operand(block, index));
-
-                // bounds check:
-                let (len, lt) = (this.temp(usize_ty.clone()), this.temp(bool_ty));
-                this.cfg.push_assign(block, source_info, // len = len(slice)
-                                     &len, Rvalue::Len(slice.clone()));
-                this.cfg.push_assign(block, source_info, // lt = idx < len
-                                     &lt, Rvalue::BinaryOp(BinOp::Lt,
-                                                           idx.clone(),
-                                                           Operand::Consume(len.clone())));
-
-                let msg = AssertMessage::BoundsCheck {
-                    len: Operand::Consume(len),
-                    index: idx.clone()
-                };
-                let success = this.assert(block, Operand::Consume(lt), true,
-                                          msg, expr_span);
-                success.and(slice.index(idx))
-                */
+                block.and(slice.index(idx))
             }
             ExprKind::Path(None, ref path) => {
                 if let Some(local) = this.get_local_from_path(&path) {
@@ -119,6 +93,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             ExprKind::Box(..) |
             ExprKind::Cast(..) |
             ExprKind::Repeat(..) |
+            ExprKind::Type(..) |
             ExprKind::AddrOf(..) |
             ExprKind::If(..) |
             ExprKind::IfLet(..) |
@@ -145,8 +120,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                 this.as_temp(block, expr)
             }
 
-            ExprKind::InPlace(..) |
-            ExprKind::Type(..) =>
+            ExprKind::InPlace(..) =>
                 panic!("not supported yet: {:?}", expr)
         }
     }
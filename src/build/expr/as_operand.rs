@@ -23,8 +23,11 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
         let this = self;
 
         match expr.node {
-            ExprKind::Path(..) => {
-                // Path operands don't need a temporary.
+            ExprKind::Path(..) |
+            ExprKind::Field(..) |
+            ExprKind::TupField(..) |
+            ExprKind::Index(..) => {
+                // Path, field, and index operands don't need a temporary.
                 let operand = unpack!(block = this.as_lvalue(block, expr));
 
                 // Only move this value if it's not `copied!(...)`.
@@ -1,6 +1,7 @@
 use build::expr::category::{Category, RvalueFunc};
+use build::expr::into::is_short_circuit;
 use build::mac::{is_mac, parse_mac};
-use build::{BlockAnd, BlockAndExtension, Builder};
+use build::{BlockAnd, BlockAndExtension, Builder, transition};
 use mir::*;
 use syntax::ast::{self, ExprKind};
 use syntax::ptr::P;
@@ -33,17 +34,56 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                     inputs: inputs
                 })
             }
-            ExprKind::Repeat { value, count } => {
+            */
+            ExprKind::Repeat(ref value, ref count) => {
+                // Like rustc, the value is evaluated once and then copied into every element, so
+                // it's fine for it to suspend. Suspending in the count is rejected before we get
+                // here, since it must be a constant.
                 let value_operand = unpack!(block = this.as_operand(block, value));
-                block.and(Rvalue::Repeat(value_operand, count))
+                block.and(Rvalue::Repeat(value_operand, count.clone()))
+            }
+            ExprKind::Closure(capture_by, _, _, _) => {
+                // Transitions inside closures have already been rejected, so the closure can be
+                // used as is. A `move` closure takes the locals it captures with it though, unless
+                // they're `Copy`, in which case it takes a copy.
+                if let ast::CaptureBy::Value = capture_by {
+                    for local in this.get_free_decls_from_closure(expr) {
+                        if !this.copy_locals.contains(&local) {
+                            this.move_lvalue(expr.span, &Lvalue::Local(local));
+                        }
+                    }
+                }
+
+                block.and(Rvalue::Closure(expr.clone()))
+            }
+            ExprKind::Type(ref value, ref ty) => {
+                // Ascription is only a hint for type inference, so evaluate the value as usual and
+                // ascribe the resulting operand.
+                let value_operand = unpack!(block = this.as_operand(block, value));
+                block.and(Rvalue::Type(value_operand, ty.clone()))
             }
-            */
             ExprKind::AddrOf(mutability, ref arg) => {
                 let arg_lvalue = unpack!(block = this.as_lvalue(block, arg));
                 block.and(Rvalue::Ref(mutability, arg_lvalue))
             }
+            ExprKind::Binary(op, _, ref rhs) if is_short_circuit(op, rhs) => {
+                // Short circuiting operators are compiled as control flow by `into`.
+                let operand = unpack!(block = this.as_operand(block, expr));
+                block.and(Rvalue::Use(operand))
+            }
             ExprKind::Binary(op, ref lhs, ref rhs) => {
-                let lhs = unpack!(block = this.as_operand(block, lhs));
+                // Paths are normally used directly as operands, but if the right hand side
+                // suspends, it could be reassigned before we get to apply the operator. So copy
+                // it into a temporary to preserve the left-to-right evaluation order.
+                let mut lhs = unpack!(block = this.as_operand(block, lhs));
+
+                if transition::contains_transition(rhs, this.options.yield_macro()) {
+                    let temp = this.temp(expr.span, "temp_binary_lhs");
+                    this.push_assign(block, expr.span, &temp, Rvalue::Use(lhs));
+                    this.move_lvalue(expr.span, &temp);
+                    lhs = Operand::Consume(temp);
+                }
+
                 let rhs = unpack!(block = this.as_operand(block, rhs));
                 this.build_binary_op(block, op, lhs, rhs)
             }
@@ -192,9 +232,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             ExprKind::While(..) |
             ExprKind::WhileLet(..) |
             ExprKind::ForLoop(..) |
-            ExprKind::Closure(..) |
             ExprKind::Loop(..) |
-            ExprKind::Repeat(..) |
             ExprKind::Vec(..) |
             ExprKind::Call(..) |
             ExprKind::MethodCall(..) |
@@ -208,7 +246,6 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             ExprKind::Box(..) |
             ExprKind::InPlace(..) |
             ExprKind::InlineAsm(..) |
-            ExprKind::Type(..) |
             ExprKind::Try(..) |
             ExprKind::Cast(..) => {
                 // these do not have corresponding `Rvalue` variants,
@@ -74,6 +74,7 @@ impl Category {
             ExprKind::Box(..) |
             ExprKind::Cast(..) |
             ExprKind::Repeat(..) |
+            ExprKind::Type(..) |
             ExprKind::AddrOf(..) |
             ExprKind::Assign(..) |
             ExprKind::AssignOp(..) |
@@ -92,8 +93,7 @@ impl Category {
                 // category, like "nonterminating"
                 Some(Category::Rvalue(RvalueFunc::Into)),
 
-            ExprKind::InPlace(..) |
-            ExprKind::Type(..) =>
+            ExprKind::InPlace(..) =>
                 panic!("not supported yet: {:?}", ek)
             
         }
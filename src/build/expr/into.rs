@@ -1,4 +1,5 @@
-use build::{BlockAnd, BlockAndExtension, Builder};
+use aster::AstBuilder;
+use build::{BlockAnd, BlockAndExtension, Builder, desugar};
 use build::expr::category::{Category, RvalueFunc};
 use mir::*;
 use syntax::ast::{self, ExprKind};
@@ -38,25 +39,20 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                     arms)
             }
             ExprKind::Loop(ref body, label) => {
+                // Only `loop` can be exited with a value, not the loops desugared into it.
+                let desugared_from = desugar::desugared_loop_kind(expr);
+
                 this.expr_loop(
                     destination,
                     block,
                     source_info,
-                    None,
-                    body,
-                    label)
-            }
-            ExprKind::While(ref cond_expr, ref body, label) => {
-                this.expr_loop(
-                    destination,
-                    block,
-                    source_info,
-                    Some(&cond_expr),
+                    desugared_from,
                     body,
                     label)
             }
             ExprKind::ForLoop(..) |
             ExprKind::IfLet(..)   |
+            ExprKind::While(..)   |
             ExprKind::WhileLet(..) => {
                 panic!("{:?} Should never reach this point - `desugar` should have desugared this.", expr);
             }
@@ -70,12 +66,24 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                     }
                 }
             }
-            ExprKind::Call(ref fun, ref args) => {
-                let fun = unpack!(block = this.as_operand(block, fun));
+            ExprKind::Call(ref fun_expr, ref args) => {
+                // A path, like a local holding a closure, is only looked up after the arguments
+                // have been evaluated. They may suspend, and the local needs to stay in the state
+                // until the call.
+                let fun = match fun_expr.node {
+                    ExprKind::Path(..) => None,
+                    _ => Some(unpack!(block = this.as_operand(block, fun_expr))),
+                };
+
                 let args = args.into_iter()
                     .map(|arg| unpack!(block = this.as_rvalue(block, arg)))
                     .collect::<Vec<_>>();
 
+                let fun = match fun {
+                    Some(fun) => fun,
+                    None => unpack!(block = this.as_operand(block, fun_expr)),
+                };
+
                 this.declare(block, expr_span, &destination);
                 this.cfg.push(block, Statement {
                     source_info: source_info,
@@ -112,6 +120,10 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                 block.unit()
             }
 
+            ExprKind::Binary(op, ref lhs, ref rhs) if is_short_circuit(op, rhs) => {
+                this.expr_short_circuit(destination, block, expr_span, op, lhs, rhs)
+            }
+
             // These cases don't actually need a destination
             ExprKind::Assign(..) |
             ExprKind::AssignOp(..) |
@@ -130,6 +142,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             ExprKind::Box(..) |
             ExprKind::Cast(..) |
             ExprKind::Repeat(..) |
+            ExprKind::Type(..) |
             ExprKind::Path(..) |
             ExprKind::Vec(..) |
             ExprKind::Tup(..) |
@@ -153,7 +166,6 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             }
 
             ExprKind::InPlace(..) |
-            ExprKind::Type(..) |
             ExprKind::Try(..) |
             ExprKind::Paren(..) => {
                 this.cx.span_bug(expr_span,
@@ -216,26 +228,88 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
         join_block.unit()
     }
 
+    /// Compile `$lhs && $rhs` and `$lhs || $rhs` as control flow, so that the right hand side
+    /// is only evaluated, and only suspends, when it's needed:
+    ///
+    /// ```
+    /// $destination = if $lhs { $rhs } else { false };  // `&&`
+    /// $destination = if $lhs { true } else { $rhs };   // `||`
+    /// ```
+    fn expr_short_circuit(&mut self,
+                          destination: Lvalue,
+                          mut block: BasicBlock,
+                          span: Span,
+                          op: ast::BinOp,
+                          lhs: &P<ast::Expr>,
+                          rhs: &P<ast::Expr>) -> BlockAnd<()> {
+        let lhs = unpack!(block = self.as_operand(block, lhs));
+
+        let mut rhs_block = self.start_new_block(span, Some("ShortCircuitRhs"));
+        let short_circuit_block = self.start_new_block(span, Some("ShortCircuit"));
+
+        let (targets, short_circuit_value) = match op.node {
+            ast::BinOpKind::And => ((rhs_block, short_circuit_block), false),
+            _ => ((short_circuit_block, rhs_block), true),
+        };
+
+        self.terminate(span, block, TerminatorKind::If {
+            cond: lhs,
+            targets: targets,
+        });
+
+        self.in_conditional_scope(span, |this| {
+            this.next_conditional_scope(span);
+
+            rhs_block = unpack!(this.into(destination.clone(), rhs_block, rhs));
+
+            this.next_conditional_scope(span);
+
+            let constant = Constant {
+                span: span,
+                literal: AstBuilder::new().span(span).lit().bool(short_circuit_value),
+            };
+
+            this.push_assign(short_circuit_block,
+                             span,
+                             &destination,
+                             Rvalue::Use(Operand::Constant(constant)));
+        });
+
+        let join_block = self.start_new_block(span, Some("ShortCircuitJoin"));
+
+        self.terminate(
+            span,
+            rhs_block,
+            TerminatorKind::Goto {
+                target: join_block,
+                end_scope: true,
+            });
+
+        self.terminate(
+            span,
+            short_circuit_block,
+            TerminatorKind::Goto {
+                target: join_block,
+                end_scope: true,
+            });
+
+        join_block.unit()
+    }
+
     fn expr_loop(&mut self,
                  destination: Lvalue,
                  block: BasicBlock,
                  source_info: SourceInfo,
-                 condition: Option<&P<ast::Expr>>,
+                 desugared_from: Option<&'static str>,
                  body: &P<ast::Block>,
                  label: Option<ast::SpannedIdent>) -> BlockAnd<()> {
-        // [block] --> [loop_block] ~~> [loop_block_end] -1-> [exit_block]
+        // [block] --> [loop_block] ~~> [body_block_end]    [exit_block]
         //                  ^                  |
-        //                  |                  0
         //                  |                  |
-        //                  |                  v
-        //           [body_block_end] <~~~ [body_block]
-        //
-        // If `opt_cond_expr` is `None`, then the graph is somewhat simplified:
+        //                  +------------------+
         //
-        // [block] --> [loop_block / body_block ] ~~> [body_block_end]    [exit_block]
-        //                         ^                          |
-        //                         |                          |
-        //                         +--------------------------+
+        // `while` loops are desugared into a `loop` that starts with `if !cond { break; }`, so
+        // the exit block is only reachable through a `break`.
 
         let this = self;
 
@@ -251,32 +325,16 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                 end_scope: false,
             });
 
-        let might_break = this.in_loop_scope(
-            label, loop_block, exit_block,
+        let break_destination = match desugared_from {
+            Some(_) => None,
+            None => Some(destination.clone()),
+        };
+
+        let loop_scope = this.in_loop_scope(
+            label, loop_block, exit_block, break_destination, desugared_from,
             move |this| {
                 let extent = this.start_new_extent();
                 let body_block_end = unpack!(this.in_scope(extent, source_info.span, loop_block, |this| {
-                    // conduct the test, if necessary
-                    let body_block;
-                    if let Some(cond_expr) = condition {
-                        // This loop has a condition, ergo its exit_block is reachable.
-                        this.find_loop_scope(source_info.span, None).might_break = true;
-
-                        let loop_block_end;
-                        let cond = unpack!(loop_block_end = this.as_operand(loop_block, cond_expr));
-                        body_block = this.start_new_block(cond_expr.span, Some("LoopBody"));
-
-                        this.terminate(
-                            cond_expr.span,
-                            loop_block_end,
-                            TerminatorKind::If {
-                                cond: cond,
-                                targets: (body_block, exit_block),
-                            });
-                    } else {
-                        body_block = loop_block;
-                    }
-
                     // The “return” value of the loop body must always be an unit, but we cannot
                     // reuse that as a “return” value of the whole loop expressions, because some
                     // loops are diverging (e.g. `loop {}`). Thus, we introduce a unit temporary as
@@ -287,28 +345,57 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                     /*
                     // FIXME(stateful): as another MIR divergence, we need to assign the return value
                     // in case we have a break.
-                    this.push_assign_unit(body.span, body_block, &tmp);
+                    this.push_assign_unit(body.span, loop_block, &tmp);
                     */
 
-                    // Execute the body, branching back to the test.
-                    this.ast_block(tmp, body_block, body)
+                    // Execute the body, branching back to the start of the loop.
+                    this.ast_block(tmp, loop_block, body)
                 }));
 
-                this.terminate(
-                    body.span,
-                    body_block_end,
+                // If the body always diverges, like a body that ends in a `break` or `return`,
+                // the end of the body can't branch back to the start of the loop, so don't add a
+                // dead back edge.
+                let kind = if this.cfg.is_reachable(body_block_end) {
                     TerminatorKind::Goto {
                         target: loop_block,
                         end_scope: true,
-                    });
+                    }
+                } else {
+                    TerminatorKind::Unreachable
+                };
+
+                this.terminate(body.span, body_block_end, kind);
             }
         );
 
-        // If the loop may reach its exit_block, we assign an empty tuple to the
-        // destination to keep the MIR well-formed.
-        if might_break {
+        if loop_scope.might_break_with_value {
+            // Every `break` assigned a value to the destination, so it's initialized once we reach
+            // the exit block. The exit block was created before the destination was initialized
+            // though, so we need to recompute its incoming decls.
+            this.initialize(exit_block, source_info.span, &destination);
+
+            let live_decls = this.find_live_decls();
+            this.cfg.block_data_mut(exit_block).incoming_decls = live_decls;
+        } else if loop_scope.might_break {
+            // If the loop may reach its exit_block, we assign an empty tuple to the
+            // destination to keep the MIR well-formed.
             this.push_assign_unit(source_info.span, exit_block, &destination);
         }
         exit_block.unit()
     }
 }
+
+/// Returns whether a `&&` or `||` needs to be compiled as control flow. Right hand sides that are
+/// just a path or a literal can't have any side effects, so we can evaluate them eagerly.
+pub fn is_short_circuit(op: ast::BinOp, rhs: &ast::Expr) -> bool {
+    match op.node {
+        ast::BinOpKind::And | ast::BinOpKind::Or => {}
+        _ => { return false; }
+    }
+
+    match rhs.node {
+        ExprKind::Path(..) | ExprKind::Lit(..) => false,
+        ExprKind::Paren(ref rhs) => is_short_circuit(op, rhs),
+        _ => true,
+    }
+}
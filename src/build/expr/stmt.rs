@@ -28,8 +28,37 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                     (loop_scope.break_block, loop_scope.extent)
                 })
             }
-            ExprKind::Break(_label, Some(ref _value)) => {
-                span_bug!(this.cx, expr_span, "break returning values is not supported yet");
+            ExprKind::Break(label, Some(ref value)) => {
+                if this.is_in_loop() {
+                    let (destination, desugared_from) = {
+                        let loop_scope = this.find_loop_scope(expr_span, label);
+                        (loop_scope.destination.clone(), loop_scope.desugared_from)
+                    };
+
+                    // Only the loops desugared from another kind of loop lack a destination.
+                    match (destination, desugared_from) {
+                        (Some(destination), _) => {
+                            let rvalue = unpack!(block = this.as_rvalue(block, value));
+                            this.push_assign_break_value(block, expr_span, &destination, rvalue);
+                        }
+                        (None, Some(desugared_from)) => {
+                            this.cx.span_err(
+                                expr_span,
+                                &format!("cannot break with a value out of a `{}` loop",
+                                         desugared_from));
+                        }
+                        (None, None) => {
+                            span_bug!(this.cx, expr_span, "`loop` has no break destination");
+                        }
+                    }
+                }
+
+                this.break_or_continue(expr_span, block, |this| {
+                    let loop_scope = this.find_loop_scope(expr_span, label);
+                    loop_scope.might_break = true;
+                    loop_scope.might_break_with_value = true;
+                    (loop_scope.break_block, loop_scope.extent)
+                })
             }
             ExprKind::Assign(ref lhs, ref rhs) => {
                 // Note: we evaluate assignments right-to-left. This
@@ -39,6 +68,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                 let rhs = unpack!(block = this.as_rvalue(block, rhs));
                 let lhs = unpack!(block = this.as_lvalue(block, lhs));
 
+                this.check_recompute_deps(expr_span, &lhs, "assigned");
                 this.push_assign(block, expr_span, &lhs, rhs);
 
                 block.unit()
@@ -50,6 +80,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
 
                 let rhs = unpack!(block = this.as_operand(block, rhs));
                 let lhs = unpack!(block = this.as_lvalue(block, lhs));
+                this.check_recompute_deps(expr_span, &lhs, "assigned");
                 let result = unpack!(
                     block = this.build_binary_op(
                         block,
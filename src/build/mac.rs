@@ -5,7 +5,7 @@ use syntax::ast;
 use syntax::ext::base::ExtCtxt;
 use syntax::ext::tt::transcribe::new_tt_reader;
 use syntax::parse::parser::Parser;
-use syntax::parse::token::Token;
+use syntax::parse::token::{self, Token, keywords};
 use syntax::ptr::P;
 
 impl<'a, 'b: 'a> Builder<'a, 'b> {
@@ -13,7 +13,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                     destination: Lvalue,
                     block: BasicBlock,
                     mac: &ast::Mac) -> Option<BlockAnd<()>> {
-        match transition::parse_mac_transition(self.cx, mac) {
+        match transition::parse_mac_transition(self.cx, self.options.yield_macro(), mac) {
             Some(transition::Transition::Suspend(expr)) => {
                 Some(self.expr_suspend(destination, block, expr))
             }
@@ -27,6 +27,13 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                     let expr = parse_mac(self.cx, mac);
                     self.copied_exprs.insert(expr.id);
 
+                    Some(self.into(destination, block, &expr))
+                } else if is_path(&mac.node.path, "recompute") {
+                    self.cx.span_err(
+                        mac.span,
+                        "`recompute!` can only be used to initialize a `let` binding");
+
+                    let expr = parse_mac(self.cx, mac);
                     Some(self.into(destination, block, &expr))
                 } else {
                     None
@@ -52,6 +59,38 @@ pub fn parse_mac(cx: &ExtCtxt, mac: &ast::Mac) -> P<ast::Expr> {
     expr
 }
 
+/// Parse the arguments of `matches!($expr, $pats if $guard)`, where the guard is optional.
+pub fn parse_mac_matches(cx: &ExtCtxt,
+                         mac: &ast::Mac) -> (P<ast::Expr>, Vec<P<ast::Pat>>, Option<P<ast::Expr>>) {
+    let rdr = new_tt_reader(
+        &cx.parse_sess().span_diagnostic,
+        None,
+        mac.node.tts.clone());
+
+    let mut parser = Parser::new(
+        cx.parse_sess(),
+        Box::new(rdr.clone()));
+
+    let expr = panictry!(parser.parse_expr());
+    panictry!(parser.expect(&Token::Comma));
+
+    let mut pats = vec![panictry!(parser.parse_pat())];
+    while parser.eat(&Token::BinOp(token::BinOpToken::Or)) {
+        pats.push(panictry!(parser.parse_pat()));
+    }
+
+    let guard = if parser.eat_keyword(keywords::If) {
+        Some(panictry!(parser.parse_expr()))
+    } else {
+        None
+    };
+
+    parser.eat(&Token::Comma);
+    panictry!(parser.expect(&Token::Eof));
+
+    (expr, pats, guard)
+}
+
 pub fn is_mac(mac: &ast::Mac, name: &str) -> bool {
     is_path(&mac.node.path, name)
 }
@@ -14,6 +14,7 @@
 //! details.
 
 use aster::ident::ToIdent;
+use build::mac::{is_mac, parse_mac};
 use build::{BlockAnd, BlockAndExtension, Builder};
 use mir::*;
 use std::ascii::AsciiExt;
@@ -31,6 +32,8 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                       discriminant: P<ast::Expr>,
                       arms: &[ast::Arm])
                       -> BlockAnd<()> {
+        // Any transitions in the discriminant are lowered before the match, so the arm patterns
+        // always bind against the value produced after resuming.
         let discriminant_lvalue = unpack!(block = self.as_operand(block, &discriminant));
 
         let targets = arms.iter()
@@ -66,7 +69,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                     let pat_locals = this.locals_from_pat(&arm.pats[0]);
 
                     for local in &pat_locals {
-                        this.initialize(block, arm.pats[0].span, &Lvalue::Local(*local));
+                        this.initialize(target.block, arm.pats[0].span, &Lvalue::Local(*local));
                     }
 
                     {
@@ -113,12 +116,29 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
 
         // optimize the case of `let x = ...`
         match irrefutable_pat.node {
-            PatKind::Ident(ast::BindingMode::ByValue(_mutability), id, _) if self.is_local(id) => {
+            PatKind::Ident(ast::BindingMode::ByValue(mutability), id, _) if self.is_local(id) => {
                 let local = self.var_indices[&irrefutable_pat.id];
                 let lvalue = Lvalue::Local(local);
 
+                if let ast::ExprKind::Mac(ref mac) = initializer.node {
+                    if is_mac(mac, "recompute") {
+                        return self.recompute_into(local, mutability, block, mac);
+                    }
+                }
+
+                if is_lit(initializer) {
+                    self.copy_locals.insert(local);
+                }
+
                 return self.into(lvalue, block, initializer);
             }
+            // `let _ = ...` doesn't bind anything, so evaluate the initializer into a temporary
+            // that's dropped once we leave the initializer's scope.
+            PatKind::Wild => {
+                let temp = self.temp(initializer.span, "temp_wild");
+
+                return self.into(temp, block, initializer);
+            }
             _ => {}
         }
         let lvalue = unpack!(block = self.as_lvalue(block, initializer));
@@ -127,6 +147,30 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                                  &lvalue)
     }
 
+    /// Initialize `let x = recompute!($expr)`. The local is left out of the states, and `$expr`
+    /// is evaluated again in every state that needs it. Transitions inside `$expr` have already
+    /// been rejected.
+    fn recompute_into(&mut self,
+                      local: Local,
+                      mutability: ast::Mutability,
+                      block: BasicBlock,
+                      mac: &ast::Mac) -> BlockAnd<()> {
+        let expr = parse_mac(self.cx, mac);
+
+        // Changes to the local would be lost when it's recomputed.
+        if mutability == ast::Mutability::Mutable {
+            self.cx.span_err(mac.span, "`recompute!` can only initialize an immutable binding");
+        }
+
+        let deps = self.get_decls_from_expr(&expr);
+        self.recompute_deps.push((local, deps));
+
+        self.local_decls[local].recompute = Some(expr.clone());
+        self.push_assign(block, mac.span, &Lvalue::Local(local), Rvalue::Recompute(expr));
+
+        block.unit()
+    }
+
     pub fn lvalue_into_pattern(&mut self,
                                block: BasicBlock,
                                irrefutable_pat: &P<ast::Pat>,
@@ -173,7 +217,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
         }
 
         match pat.node {
-            PatKind::Ident(ast::BindingMode::ByValue(mutability), id, _) => {
+            PatKind::Ident(ast::BindingMode::ByValue(mutability), id, ref subpattern) => {
                 // Consider only lower case identities as a variable.
                 if self.is_local(id) {
                     if var_scope.is_none() {
@@ -190,6 +234,23 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                         pat.id,
                         ty.clone());
                 }
+
+                // At-bindings like `x @ Some(y)` also bind everything in their subpattern.
+                if let Some(ref subpattern) = *subpattern {
+                    var_scope = self.declare_bindings(
+                        var_scope,
+                        scope_span,
+                        subpattern,
+                        &None);
+                }
+            }
+
+            PatKind::Box(ref subpattern) => {
+                var_scope = self.declare_bindings(
+                    var_scope,
+                    scope_span,
+                    subpattern,
+                    &None);
             }
 
             PatKind::Struct(_, ref subpatterns, _) => {
@@ -230,7 +291,6 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             PatKind::Wild => { }
 
             PatKind::Ident(ast::BindingMode::ByRef(_), _, _) |
-            PatKind::Box(_) |
             PatKind::Ref(_, _) |
             PatKind::Mac(_) => {
                 span_bug!(self.cx, pat.span, "Cannot handle pat {:?}", pat)
@@ -262,7 +322,11 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             ty: var_ty,
             shadowed_decl: shadowed_decl,
             source_info: source_info,
+            recompute: None,
         });
+        if self.local_decls[var].ty.as_ref().map_or(false, |ty| is_copy_ty(ty)) {
+            self.copy_locals.insert(var);
+        }
         let extent = self.extent_of_innermost_scope();
         self.schedule_drop(source_info.span, extent, &Lvalue::Local(var));
         self.var_indices.insert(var_id, var);
@@ -308,3 +372,34 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
         first_char == first_char.to_ascii_lowercase()
     }
 }
+
+/// Whether `ty` is a primitive, a shared reference, a raw pointer, or a tuple of those, which are
+/// all `Copy`.
+fn is_copy_ty(ty: &ast::Ty) -> bool {
+    match ty.node {
+        ast::TyKind::Path(None, ref path) => {
+            let primitives = [
+                "bool", "char", "f32", "f64", "i8", "i16", "i32", "i64", "isize", "u8", "u16",
+                "u32", "u64", "usize",
+            ];
+
+            !path.global &&
+                path.segments.len() == 1 &&
+                path.segments[0].parameters.is_empty() &&
+                primitives.contains(&&*path.segments[0].identifier.name.as_str())
+        }
+        ast::TyKind::Rptr(_, ast::MutTy { mutbl: ast::Mutability::Immutable, .. }) |
+        ast::TyKind::Ptr(_) => true,
+        ast::TyKind::Tup(ref tys) => tys.iter().all(|ty| is_copy_ty(ty)),
+        _ => false,
+    }
+}
+
+/// Whether `expr` is a literal, possibly negated. Literals are all `Copy`.
+fn is_lit(expr: &ast::Expr) -> bool {
+    match expr.node {
+        ast::ExprKind::Lit(_) => true,
+        ast::ExprKind::Unary(ast::UnOp::Neg, ref expr) => is_lit(expr),
+        _ => false,
+    }
+}
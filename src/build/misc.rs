@@ -30,6 +30,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             ty: None,
             shadowed_decl: None,
             source_info: source_info,
+            recompute: None,
         });
         let lvalue = Lvalue::Local(temp);
         let extent = self.extent_of_innermost_scope();
@@ -1,6 +1,7 @@
 use build::scope::ConditionalScope;
 use data_structures::indexed_vec::{Idx, IndexVec};
 use mir::*;
+use options::Options;
 use std::collections::{HashMap, HashSet};
 use std::u32;
 use syntax::ast;
@@ -12,6 +13,7 @@ pub struct Builder<'a, 'b: 'a> {
     cx: &'a ExtCtxt<'b>,
     cfg: CFG,
     state_machine_kind: StateMachineKind,
+    options: Options,
 
     fn_span: Span,
 
@@ -47,11 +49,28 @@ pub struct Builder<'a, 'b: 'a> {
 
     moved_exprs: HashSet<ast::NodeId>,
     copied_exprs: HashSet<ast::NodeId>,
+
+    /// Locals known to be `Copy`, from their type annotation or a literal initializer. A `move`
+    /// closure only copies these, so they stay live after it.
+    copy_locals: HashSet<Local>,
+
+    /// The locals each `recompute!` initializer reads, which can't change while the recomputed
+    /// local is live.
+    recompute_deps: Vec<(Local, Vec<Local>)>,
+
+    /// Items declared in the body, which are hoisted into the generated function.
+    items: Vec<P<ast::Item>>,
+
+    /// Functions declared in the body that return `!`, so the code after a call to one of them is
+    /// unreachable.
+    diverging_fns: HashSet<ast::Name>,
 }
 
 #[derive(Debug)]
 pub struct CFG {
     basic_blocks: IndexVec<BasicBlock, BasicBlockData>,
+    /// Whether each block can be reached from the start block through the terminators set so far.
+    reachable: IndexVec<BasicBlock, bool>,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
@@ -140,16 +159,34 @@ macro_rules! unpack {
 
 pub fn construct_fn(cx: &ExtCtxt,
                     state_machine_kind: StateMachineKind,
+                    options: Options,
                     span: Span,
                     fn_decl: FunctionDecl,
                     ast_block: P<ast::Block>) -> Mir {
+    // Transitions left in positions we can't suspend from would just cascade into confusing
+    // "macro undefined" errors once they're passed through, so stop here if we found any.
+    if !transition::check_transition_positions(cx, options.yield_macro(), &ast_block) {
+        cx.parse_sess.span_diagnostic.abort_if_errors();
+    }
+
+    // Like the above, rustc's own error for an infinitely sized state machine is a confusing
+    // cycle between the generated types.
+    if !recursion::check_recursion(cx,
+                                    state_machine_kind,
+                                    options.yield_macro(),
+                                    &fn_decl,
+                                    &ast_block) {
+        cx.parse_sess.span_diagnostic.abort_if_errors();
+    }
+
     let (fn_decl, ast_block) = desugar::desugar_block(
         cx,
         state_machine_kind,
+        &options,
         fn_decl,
         ast_block);
 
-    let mut builder = Builder::new(cx, span, state_machine_kind);
+    let mut builder = Builder::new(cx, span, state_machine_kind, options);
 
     let call_site_extent = builder.extents.push(CodeExtentData::CallSiteScope);
     let arg_extent = builder.extents.push(CodeExtentData::ParameterScope);
@@ -180,12 +217,14 @@ pub fn construct_fn(cx: &ExtCtxt,
 impl<'a, 'b: 'a> Builder<'a, 'b> {
     fn new(cx: &'a ExtCtxt<'b>,
            span: Span,
-           state_machine_kind: StateMachineKind) -> Self {
+           state_machine_kind: StateMachineKind,
+           options: Options) -> Self {
         let mut builder = Builder {
             cx: cx,
-            cfg: CFG { basic_blocks: IndexVec::new() },
+            cfg: CFG { basic_blocks: IndexVec::new(), reachable: IndexVec::new() },
             fn_span: span,
             state_machine_kind: state_machine_kind,
+            options: options,
             scopes: vec![],
             scope_auxiliary: IndexVec::new(),
             visibility_scopes: IndexVec::new(),
@@ -199,6 +238,10 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             cached_return_block: None,
             moved_exprs: HashSet::new(),
             copied_exprs: HashSet::new(),
+            copy_locals: HashSet::new(),
+            recompute_deps: vec![],
+            items: vec![],
+            diverging_fns: HashSet::new(),
         };
 
         assert_eq!(builder.start_new_block(span, Some("Start")), START_BLOCK);
@@ -211,7 +254,11 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
     fn finish(self, fn_decl: FunctionDecl) -> Mir {
         for (index, block) in self.cfg.basic_blocks.iter().enumerate() {
             if block.terminator.is_none() {
-                span_bug!(self.cx, self.fn_span, "no terminator on block {:?}", index);
+                span_bug!(self.cx,
+                          block.span,
+                          "no terminator on block {:?} ({})",
+                          index,
+                          block.name.unwrap_or("<unnamed>"));
             }
         }
 
@@ -248,9 +295,11 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
 
         Mir::new(
             self.state_machine_kind,
+            self.options,
             self.cfg.basic_blocks,
             self.visibility_scopes,
             self.local_decls,
+            self.items,
             self.fn_span,
             fn_decl,
         )
@@ -319,8 +368,14 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
         match self.cached_return_block {
             Some(rb) => rb,
             None => {
+                // The return pointer is the only thing that flows into the end block, no matter
+                // how deeply nested the `return` is that first asks for it.
                 let span = self.fn_span;
-                let rb = self.start_new_block(span, Some("End"));
+                let mut decls = LiveDeclMap::new();
+                decls.insert(self.local_decls[RETURN_POINTER].source_info.scope,
+                             vec![LiveDecl::Active(RETURN_POINTER)]);
+
+                let rb = self.cfg.start_new_block(span, Some("End"), decls);
                 self.cached_return_block = Some(rb);
                 rb
             }
@@ -341,6 +396,7 @@ mod mac;
 mod matches;
 mod misc;
 mod moved;
+mod recursion;
 mod scope;
 mod suspend;
 mod transition;
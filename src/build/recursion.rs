@@ -0,0 +1,160 @@
+use build::transition::{self, Transition};
+use mir::*;
+use syntax::ast::{self, ExprKind, StmtKind};
+use syntax::codemap::Span;
+use syntax::ext::base::ExtCtxt;
+use syntax::parse::token::keywords;
+use syntax::visit;
+
+/// Report an error for every call a state machine returning `impl Trait` makes to itself whose
+/// result is held across a suspend, and return whether there were none. The state of such a call
+/// would be stored in the state of the caller, so the state machine's type would be infinitely
+/// sized. rustc only reports this as a cycle between the anonymous types, which doesn't point at
+/// the call. Recursive calls that are used up before the next suspend are fine.
+pub fn check_recursion(cx: &ExtCtxt,
+                       state_machine_kind: StateMachineKind,
+                       yield_macro: &str,
+                       fn_decl: &FunctionDecl,
+                       block: &ast::Block) -> bool {
+    match fn_decl.return_ty().node {
+        ast::TyKind::ImplTrait(_) => {}
+        _ => { return true; }
+    }
+
+    let mut visitor = RecursionVisitor {
+        cx: cx,
+        state_machine_kind: state_machine_kind,
+        yield_macro: yield_macro,
+        ident: fn_decl.ident(),
+        has_errors: false,
+    };
+
+    visit::Visitor::visit_block(&mut visitor, block);
+    !visitor.has_errors
+}
+
+struct RecursionVisitor<'a, 'b: 'a> {
+    cx: &'a ExtCtxt<'b>,
+    state_machine_kind: StateMachineKind,
+    yield_macro: &'a str,
+    ident: ast::Ident,
+    has_errors: bool,
+}
+
+impl<'a, 'b: 'a> RecursionVisitor<'a, 'b> {
+    fn report(&mut self, span: Span) {
+        let boxed_ty = match self.state_machine_kind {
+            StateMachineKind::Generator => "Box<Iterator<Item=T>>",
+            StateMachineKind::Async => "Box<Future<Item=T, Error=E>>",
+        };
+
+        self.cx.struct_span_err(
+            span,
+            &format!("recursive call to `{}` would make the state machine infinitely sized",
+                     self.ident))
+            .help(&format!("return a `{}` instead of `impl Trait`, so the recursive state is \
+                            boxed",
+                           boxed_ty))
+            .emit();
+
+        self.has_errors = true;
+    }
+
+    /// Whether `path` names this function, either directly or as `Self::name`.
+    fn is_own_path(&self, path: &ast::Path) -> bool {
+        let segments = &path.segments;
+
+        match segments.len() {
+            1 => !path.global && segments[0].identifier.name == self.ident.name,
+            2 => {
+                segments[0].identifier.name == keywords::SelfType.name() &&
+                    segments[1].identifier.name == self.ident.name
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the value of `expr` is the result of a call to this function, like `name(...)` or
+    /// `self.name(...)`. Method calls on the result aren't followed, since we can't tell adapters
+    /// that hold on to it from methods like `count` that use it up.
+    fn holds_own_call(&self, expr: &ast::Expr) -> bool {
+        match expr.node {
+            ExprKind::Call(ref fun, _) => {
+                match fun.node {
+                    ExprKind::Path(None, ref path) => self.is_own_path(path),
+                    _ => false,
+                }
+            }
+            ExprKind::MethodCall(ident, _, ref args) => {
+                let is_self_receiver = match args[0].node {
+                    ExprKind::Path(None, ref path) => {
+                        path.segments.len() == 1 &&
+                            path.segments[0].identifier.name == keywords::SelfValue.name()
+                    }
+                    _ => false,
+                };
+
+                is_self_receiver && ident.node.name == self.ident.name
+            }
+            ExprKind::Paren(ref expr) |
+            ExprKind::AddrOf(_, ref expr) => self.holds_own_call(expr),
+            _ => false,
+        }
+    }
+}
+
+impl<'a, 'b: 'a> visit::Visitor for RecursionVisitor<'a, 'b> {
+    fn visit_block(&mut self, block: &ast::Block) {
+        // A binding initialized with a recursive call is held across any suspends that follow it.
+        for (index, stmt) in block.stmts.iter().enumerate() {
+            if let StmtKind::Local(ref local) = stmt.node {
+                if let Some(ref init) = local.init {
+                    if self.holds_own_call(init) &&
+                        transition::stmts_contain_transition(&block.stmts[index + 1..],
+                                                             self.yield_macro) {
+                        self.report(init.span);
+                    }
+                }
+            }
+        }
+
+        visit::walk_block(self, block)
+    }
+
+    fn visit_expr(&mut self, expr: &ast::Expr) {
+        match expr.node {
+            ExprKind::ForLoop(_, ref iter, ref body, _) => {
+                // The iterator is held across any suspends in the loop body.
+                if self.holds_own_call(iter) &&
+                    transition::stmts_contain_transition(&body.stmts, self.yield_macro) {
+                    self.report(iter.span);
+                }
+            }
+            ExprKind::Closure(..) => { return; }
+            _ => {}
+        }
+
+        visit::walk_expr(self, expr)
+    }
+
+    fn visit_item(&mut self, _item: &ast::Item) { }
+
+    fn visit_mac(&mut self, mac: &ast::Mac) {
+        match transition::parse_mac_transition(self.cx, self.yield_macro, mac) {
+            // These keep their argument across the suspends they make.
+            Some(Transition::YieldFrom(expr)) |
+            Some(Transition::Await(expr)) => {
+                if self.holds_own_call(&expr) {
+                    self.report(expr.span);
+                }
+
+                self.visit_expr(&expr);
+            }
+            Some(Transition::Yield(expr)) |
+            Some(Transition::Suspend(expr)) => {
+                self.visit_expr(&expr);
+            }
+            None => {}
+        }
+    }
+}
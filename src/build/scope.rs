@@ -20,6 +20,8 @@ use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::mem;
 use syntax::ast;
 use syntax::codemap::Span;
+use syntax::ptr::P;
+use syntax::visit;
 
 #[derive(Debug)]
 pub struct Scope {
@@ -85,8 +87,15 @@ pub struct LoopScope {
     /// Block to branch into when the loop terminates (either by being `break`-en out from, or by
     /// having its condition to become false)
     pub break_block: BasicBlock,
+    /// Where a `break` with a value should store its value. This is only set for `loop`, since
+    /// `while` loops can only evaluate to `()`.
+    pub destination: Option<Lvalue>,
+    /// The kind of loop, like `for`, that this loop was desugared from, if any.
+    pub desugared_from: Option<&'static str>,
     /// Indicates the reachability of the break_block for this loop
-    pub might_break: bool
+    pub might_break: bool,
+    /// Indicates if the loop was exited by a `break` with a value
+    pub might_break_with_value: bool,
 }
 
 impl<'a, 'b: 'a> Builder<'a, 'b> {
@@ -96,7 +105,9 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                             label: Option<ast::SpannedIdent>,
                             loop_block: BasicBlock,
                             break_block: BasicBlock,
-                            f: F) -> bool
+                            destination: Option<Lvalue>,
+                            desugared_from: Option<&'static str>,
+                            f: F) -> LoopScope
         where F: FnOnce(&mut Builder)
     {
         debug!("in_loop_scope(label={:?}, loop_block={:?}, break_block={:?})", label, loop_block, break_block);
@@ -107,13 +118,16 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             label: label,
             continue_block: loop_block,
             break_block: break_block,
-            might_break: false
+            destination: destination,
+            desugared_from: desugared_from,
+            might_break: false,
+            might_break_with_value: false,
         };
         self.loop_scopes.push(loop_scope);
         f(self);
         let loop_scope = self.loop_scopes.pop().unwrap();
         assert!(loop_scope.extent == extent);
-        loop_scope.might_break
+        loop_scope
     }
 
     /// Start a loop scope, which tracks where `continue` and `break`
@@ -316,10 +330,13 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                 loop_scopes.iter_mut().rev().next()
             }
             Some(label) => {
-                // otherwise, find the loop-scope with the correct id
+                // otherwise, find the loop-scope with the correct id. Note we only compare the
+                // identifiers, since the label's span differs from the one on the loop.
                 loop_scopes.iter_mut()
                            .rev()
-                           .filter(|loop_scope| loop_scope.label == Some(label))
+                           .filter(|loop_scope| {
+                               loop_scope.label.map(|l| l.node) == Some(label.node)
+                           })
                            .next()
             }
         };
@@ -519,6 +536,44 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                     kind: StatementKind::Assign(lvalue.clone(), rvalue),
                 });
             }
+            Lvalue::Projection(_) => {
+                // Writing through a projection doesn't initialize anything, so the base must
+                // already be live.
+                self.cfg.push(block, Statement {
+                    source_info: self.source_info(span),
+                    kind: StatementKind::Assign(lvalue.clone(), rvalue),
+                });
+            }
+            Lvalue::Static(_) => {
+                span_bug!(self.cx, span, "cannot assign yet: {:?}", lvalue)
+            }
+        }
+    }
+
+    /// Assign the value of a `break` to the loop's destination. Unlike `push_assign`, this doesn't
+    /// mark the destination as initialized, since the `break` may only happen on some paths
+    /// through the loop. Instead, the loop initializes the destination in its exit block.
+    pub fn push_assign_break_value(&mut self,
+                                   block: BasicBlock,
+                                   span: Span,
+                                   lvalue: &Lvalue,
+                                   rvalue: Rvalue) {
+        debug!("push_assign_break_value: block={:?} lvalue={:?} rvalue={:?}", block, lvalue, rvalue);
+
+        match *lvalue {
+            Lvalue::Local(local) => {
+                let source_info = self.source_info(span);
+
+                self.cfg.push(block, Statement {
+                    source_info: source_info,
+                    kind: StatementKind::Declare(local),
+                });
+
+                self.cfg.push(block, Statement {
+                    source_info: source_info,
+                    kind: StatementKind::Assign(lvalue.clone(), rvalue),
+                });
+            }
             _ => {
                 span_bug!(self.cx, span, "cannot assign yet: {:?}", lvalue)
             }
@@ -794,6 +849,8 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                 &format!("trying to move an uninitialized local {:?}?", local));
         }
 
+        self.check_recompute_deps(span, &Lvalue::Local(local), "moved");
+
         for scope in self.scopes.iter_mut().rev() {
             scope.moved_decls.insert(local);
 
@@ -803,7 +860,39 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
         }
     }
 
-    /*
+    /// Reject changing a local that a `recompute!` initializer reads while the recomputed local
+    /// is still live, since recomputing it would then give a different value, or fail to compile
+    /// once the local has been moved.
+    pub fn check_recompute_deps(&self, span: Span, lvalue: &Lvalue, change: &str) {
+        let local = match *lvalue {
+            Lvalue::Local(local) => local,
+            Lvalue::Projection(ref projection) => {
+                return self.check_recompute_deps(span, &projection.base, change);
+            }
+            Lvalue::Static(_) => { return; }
+        };
+
+        for &(recomputed, ref deps) in &self.recompute_deps {
+            if !deps.contains(&local) {
+                continue;
+            }
+
+            let is_live = self.scopes.iter()
+                .find(|scope| scope.decls.contains(&recomputed))
+                .map_or(false, |scope| !scope.moved_decls.contains(&recomputed));
+
+            if is_live {
+                self.cx.span_err(
+                    span,
+                    &format!("`{}` can't be {} while `{}` is recomputed from it",
+                             self.local_decls[local].name,
+                             change,
+                             self.local_decls[recomputed].name));
+            }
+        }
+    }
+
+    /// Returns the locals mentioned by paths in the expression.
     pub fn get_decls_from_expr(&self, expr: &P<ast::Expr>) -> Vec<Local> {
         struct Visitor<'a, 'b: 'a> {
             builder: &'a Builder<'a, 'b>,
@@ -836,7 +925,104 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
 
         visitor.locals
     }
-    */
+
+    /// Returns the locals a closure captures, which are the locals mentioned by paths in its body
+    /// that aren't bound inside of the closure itself.
+    pub fn get_free_decls_from_closure(&self, closure: &ast::Expr) -> Vec<Local> {
+        struct Visitor<'a, 'b: 'a> {
+            builder: &'a Builder<'a, 'b>,
+            bound: Vec<Vec<ast::Name>>,
+            locals: Vec<Local>,
+        }
+
+        impl<'a, 'b: 'a> Visitor<'a, 'b> {
+            fn in_frame<F>(&mut self, pats: &[&ast::Pat], f: F)
+                where F: FnOnce(&mut Self)
+            {
+                let mut frame = vec![];
+                for pat in pats {
+                    pat_names(pat, &mut frame);
+                }
+
+                self.bound.push(frame);
+                f(self);
+                self.bound.pop();
+            }
+        }
+
+        impl<'a, 'b: 'a> visit::Visitor for Visitor<'a, 'b> {
+            fn visit_expr(&mut self, expr: &ast::Expr) {
+                match expr.node {
+                    ast::ExprKind::Path(None, ref path) => {
+                        let is_bound = path.segments.len() == 1 && self.bound.iter()
+                            .any(|frame| frame.contains(&path.segments[0].identifier.name));
+
+                        if !is_bound {
+                            if let Some(local) = self.builder.get_local_from_path(path) {
+                                if !self.locals.contains(&local) {
+                                    self.locals.push(local);
+                                }
+                            }
+                        }
+                    }
+                    ast::ExprKind::Closure(_, ref fn_decl, _, _) => {
+                        let pats = fn_decl.inputs.iter().map(|arg| &*arg.pat).collect::<Vec<_>>();
+                        self.in_frame(&pats, |this| visit::walk_expr(this, expr));
+                    }
+                    ast::ExprKind::ForLoop(ref pat, ref iter, ref body, _) => {
+                        self.visit_expr(iter);
+                        self.in_frame(&[pat], |this| this.visit_block(body));
+                    }
+                    ast::ExprKind::IfLet(ref pat, ref value, ref then_block, ref else_expr) => {
+                        self.visit_expr(value);
+                        self.in_frame(&[pat], |this| this.visit_block(then_block));
+                        if let Some(ref else_expr) = *else_expr {
+                            self.visit_expr(else_expr);
+                        }
+                    }
+                    ast::ExprKind::WhileLet(ref pat, ref value, ref body, _) => {
+                        self.visit_expr(value);
+                        self.in_frame(&[pat], |this| this.visit_block(body));
+                    }
+                    _ => {
+                        visit::walk_expr(self, expr);
+                    }
+                }
+            }
+
+            fn visit_block(&mut self, block: &ast::Block) {
+                // Bindings of `let` statements are added to the block's frame as we go.
+                self.in_frame(&[], |this| visit::walk_block(this, block));
+            }
+
+            fn visit_local(&mut self, local: &ast::Local) {
+                if let Some(ref init) = local.init {
+                    self.visit_expr(init);
+                }
+
+                pat_names(&local.pat, self.bound.last_mut().unwrap());
+            }
+
+            fn visit_arm(&mut self, arm: &ast::Arm) {
+                let pats = arm.pats.iter().map(|pat| &**pat).collect::<Vec<_>>();
+                self.in_frame(&pats, |this| visit::walk_arm(this, arm));
+            }
+
+            fn visit_mac(&mut self, _mac: &ast::Mac) { }
+
+            fn visit_item(&mut self, _item: &ast::Item) { }
+        }
+
+        let mut visitor = Visitor {
+            builder: self,
+            bound: vec![],
+            locals: Vec::new(),
+        };
+
+        visit::Visitor::visit_expr(&mut visitor, closure);
+
+        visitor.locals
+    }
 
     pub fn get_local_from_path(&self, path: &ast::Path) -> Option<Local> {
         if !path.global && path.segments.len() == 1 {
@@ -860,6 +1046,25 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
     }
 }
 
+/// Collect the names bound by a pattern.
+fn pat_names(pat: &ast::Pat, names: &mut Vec<ast::Name>) {
+    struct PatVisitor<'a> {
+        names: &'a mut Vec<ast::Name>,
+    }
+
+    impl<'a> visit::Visitor for PatVisitor<'a> {
+        fn visit_pat(&mut self, pat: &ast::Pat) {
+            if let ast::PatKind::Ident(_, ident, _) = pat.node {
+                self.names.push(ident.node.name);
+            }
+
+            visit::walk_pat(self, pat);
+        }
+    }
+
+    visit::Visitor::visit_pat(&mut PatVisitor { names: names }, pat);
+}
+
 fn drop_decl(cfg: &mut CFG,
              block: BasicBlock,
              scope: &Scope,
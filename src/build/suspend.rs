@@ -1,7 +1,8 @@
 use build::{BlockAnd, BlockAndExtension, Builder};
 use mir::*;
-use syntax::ast;
+use syntax::ast::{self, ExprKind};
 use syntax::ptr::P;
+use syntax::visit;
 
 impl<'a, 'b: 'a> Builder<'a, 'b> {
     /// Generate code to suspend the coroutine.
@@ -11,6 +12,8 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                         rvalue: P<ast::Expr>) -> BlockAnd<()> {
         let rvalue_span = rvalue.span;
 
+        self.check_borrowed_state(&rvalue);
+
         let rvalue = unpack!(block = self.as_rvalue(block, &rvalue));
         let next_block = self.start_new_block(rvalue_span, Some("AfterSuspend"));
 
@@ -25,4 +28,95 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
 
         next_block.unit()
     }
+
+    /// Every local lives in the state, which gets moved into the next state when we suspend, so
+    /// the suspended value cannot borrow from a local. Rust would reject this anyway, but the
+    /// error would point into the generated code, so report it here instead.
+    ///
+    /// Borrowing through a local that holds a reference, like `&items[0]` for an argument
+    /// `items: &[T]`, is fine since the borrow doesn't point into the state.
+    fn check_borrowed_state(&self, expr: &ast::Expr) {
+        struct BorrowVisitor<'c, 'a: 'c, 'b: 'a> {
+            builder: &'c Builder<'a, 'b>,
+        }
+
+        impl<'c, 'a: 'c, 'b: 'a> visit::Visitor for BorrowVisitor<'c, 'a, 'b> {
+            fn visit_expr(&mut self, expr: &ast::Expr) {
+                match expr.node {
+                    ExprKind::AddrOf(_, ref arg) => {
+                        if let Some(local) = self.builder.borrowed_state_local(arg) {
+                            let name = self.builder.local_decls[local].name;
+
+                            self.builder.cx.struct_span_err(
+                                expr.span,
+                                &format!("cannot yield a reference to `{}`, which is owned by \
+                                          the {}",
+                                         name,
+                                         self.builder.state_machine_kind))
+                                .help(&format!("yield an owned value instead, such as \
+                                                `{}.clone()` or `{}[..].to_vec()`",
+                                               name,
+                                               name))
+                                .emit();
+                        }
+                    }
+                    ExprKind::Closure(..) => { }
+                    _ => {
+                        visit::walk_expr(self, expr)
+                    }
+                }
+            }
+
+            fn visit_item(&mut self, _item: &ast::Item) { }
+
+            fn visit_mac(&mut self, _mac: &ast::Mac) { }
+        }
+
+        let mut visitor = BorrowVisitor {
+            builder: self,
+        };
+
+        visit::Visitor::visit_expr(&mut visitor, expr);
+    }
+
+    /// Returns the local a borrow of `expr` would point into, if any.
+    fn borrowed_state_local(&self, expr: &ast::Expr) -> Option<Local> {
+        let mut base = expr;
+        let mut projected = false;
+
+        loop {
+            match base.node {
+                ExprKind::Field(ref expr, _) |
+                ExprKind::TupField(ref expr, _) |
+                ExprKind::Index(ref expr, _) => {
+                    base = expr;
+                    projected = true;
+                }
+                ExprKind::Paren(ref expr) => {
+                    base = expr;
+                }
+                ExprKind::Path(None, ref path) => {
+                    let local = match self.get_local_from_path(path) {
+                        Some(local) => local,
+                        None => { return None; }
+                    };
+
+                    // Directly borrowing a local always points into the state. Otherwise we can
+                    // only tell if the local is a reference if it has a type annotation.
+                    let owned = !projected || match self.local_decls[local].ty {
+                        Some(ref ty) => {
+                            match ty.node {
+                                ast::TyKind::Rptr(..) | ast::TyKind::Ptr(..) => false,
+                                _ => true,
+                            }
+                        }
+                        None => false,
+                    };
+
+                    return if owned { Some(local) } else { None };
+                }
+                _ => { return None; }
+            }
+        }
+    }
 }
@@ -1,126 +1,261 @@
-use build::mac::{parse_mac, is_path};
-use syntax::ast;
+use build::mac::{parse_mac, parse_mac_matches, is_path};
+use syntax::ast::{self, ExprKind};
 use syntax::ext::base::ExtCtxt;
+use syntax::parse::token::Token;
 use syntax::ptr::P;
-
-/*
-use build::Builder;
+use syntax::tokenstream::TokenTree;
 use syntax::visit;
 
-impl<'a, 'b: 'a> Builder<'a, 'b> {
-    pub fn contains_transition<E: ContainsTransition>(&self, expr: E) -> bool {
-        expr.contains_transition(self.is_inside_loop())
-    }
-}
+/// Returns whether the expression contains a transition that may suspend the state machine, like
+/// `yield_!(...)`. Closures and items are not searched, since any transitions inside them don't
+/// belong to this state machine.
+pub fn contains_transition(expr: &ast::Expr, yield_macro: &str) -> bool {
+    let mut visitor = ContainsTransitionVisitor {
+        yield_macro: yield_macro,
+        contains_transition: false,
+    };
 
-pub trait ContainsTransition {
-    fn contains_transition(&self, inside_loop: bool) -> bool;
+    visit::Visitor::visit_expr(&mut visitor, expr);
+    visitor.contains_transition
 }
 
-impl<'a> ContainsTransition for &'a P<ast::Block> {
-    fn contains_transition(&self, inside_loop: bool) -> bool {
-        (**self).contains_transition(inside_loop)
-    }
-}
+/// Like `contains_transition`, but for a sequence of statements.
+pub fn stmts_contain_transition(stmts: &[ast::Stmt], yield_macro: &str) -> bool {
+    let mut visitor = ContainsTransitionVisitor {
+        yield_macro: yield_macro,
+        contains_transition: false,
+    };
 
-impl ContainsTransition for ast::Block {
-    fn contains_transition(&self, inside_loop: bool) -> bool {
-        let mut visitor = ContainsTransitionVisitor::new(inside_loop);
-
-        visit::Visitor::visit_block(&mut visitor, self);
-        visitor.contains_transition
+    for stmt in stmts {
+        visit::Visitor::visit_stmt(&mut visitor, stmt);
     }
-}
 
-impl ContainsTransition for ast::Stmt {
-    fn contains_transition(&self, inside_loop: bool) -> bool {
-        let mut visitor = ContainsTransitionVisitor::new(inside_loop);
+    visitor.contains_transition
+}
 
-        visit::Visitor::visit_stmt(&mut visitor, self);
-        visitor.contains_transition
-    }
+struct ContainsTransitionVisitor<'a> {
+    yield_macro: &'a str,
+    contains_transition: bool,
 }
 
-impl<'a> ContainsTransition for &'a P<ast::Expr> {
-    fn contains_transition(&self, inside_loop: bool) -> bool {
-        (**self).contains_transition(inside_loop)
+impl<'a> visit::Visitor for ContainsTransitionVisitor<'a> {
+    fn visit_expr(&mut self, expr: &ast::Expr) {
+        match expr.node {
+            ExprKind::Closure(..) => { }
+            _ => {
+                visit::walk_expr(self, expr)
+            }
+        }
     }
-}
 
-impl ContainsTransition for ast::Expr {
-    fn contains_transition(&self, inside_loop: bool) -> bool {
-        let mut visitor = ContainsTransitionVisitor::new(inside_loop);
+    fn visit_item(&mut self, _item: &ast::Item) { }
 
-        visit::Visitor::visit_expr(&mut visitor, self);
-        visitor.contains_transition
+    fn visit_mac(&mut self, mac: &ast::Mac) {
+        if is_transition_path(&mac.node.path, self.yield_macro) {
+            self.contains_transition = true;
+        }
     }
 }
 
-impl ContainsTransition for ast::Mac {
-    fn contains_transition(&self, _inside_loop: bool) -> bool {
-        is_transition_path(&self.node.path)
-    }
+/// Report an error for every transition that's in a position we can't suspend from, like a match
+/// guard or an array length, and return whether the block is free of them. Transitions in these
+/// positions would otherwise be passed through to the generated code untouched.
+pub fn check_transition_positions(cx: &ExtCtxt, yield_macro: &str, block: &ast::Block) -> bool {
+    let mut visitor = TransitionPositionVisitor {
+        cx: cx,
+        yield_macro: yield_macro,
+        unsupported_position: None,
+        has_errors: false,
+    };
+
+    visit::Visitor::visit_block(&mut visitor, block);
+    !visitor.has_errors
 }
 
-struct ContainsTransitionVisitor {
-    inside_loop: bool,
-    contains_transition: bool,
+struct TransitionPositionVisitor<'a, 'b: 'a> {
+    cx: &'a ExtCtxt<'b>,
+    yield_macro: &'a str,
+    /// A description of the unsupported position we're currently visiting, if any.
+    unsupported_position: Option<&'static str>,
+    has_errors: bool,
 }
 
-impl ContainsTransitionVisitor {
-    fn new(inside_loop: bool) -> Self {
-        ContainsTransitionVisitor {
-            inside_loop: inside_loop,
-            contains_transition: false,
-        }
+impl<'a, 'b: 'a> TransitionPositionVisitor<'a, 'b> {
+    fn in_unsupported_position<F>(&mut self, position: &'static str, f: F)
+        where F: FnOnce(&mut Self)
+    {
+        // Report the outermost unsupported position, since that's what needs to be rewritten.
+        let outer_position = self.unsupported_position;
+        self.unsupported_position = Some(outer_position.unwrap_or(position));
+        f(self);
+        self.unsupported_position = outer_position;
     }
 }
 
-impl visit::Visitor for ContainsTransitionVisitor {
-    fn visit_stmt(&mut self, stmt: &ast::Stmt) {
-        match stmt.node {
-            StmtKind::Mac(ref mac) if is_transition_path(&(*mac).0.node.path) => {
-                self.contains_transition = true;
+impl<'a, 'b: 'a> visit::Visitor for TransitionPositionVisitor<'a, 'b> {
+    fn visit_expr(&mut self, expr: &ast::Expr) {
+        match expr.node {
+            ExprKind::Closure(..) => {
+                // A closure is its own function, so it can't suspend the state machine it's
+                // defined in.
+                let mut visitor = ClosureTransitionVisitor {
+                    cx: self.cx,
+                    yield_macro: self.yield_macro,
+                    has_errors: false,
+                };
+
+                visit::walk_expr(&mut visitor, expr);
+                self.has_errors |= visitor.has_errors;
+            }
+            ExprKind::Repeat(ref element, ref count) => {
+                self.visit_expr(element);
+                self.in_unsupported_position("an array length", |this| this.visit_expr(count));
             }
             _ => {
-                visit::walk_stmt(self, stmt)
+                visit::walk_expr(self, expr)
             }
         }
     }
 
-    fn visit_expr(&mut self, expr: &ast::Expr) {
-        match expr.node {
-            ExprKind::Ret(Some(_)) | ExprKind::Assign(..) => {
-                self.contains_transition = true;
+    fn visit_arm(&mut self, arm: &ast::Arm) {
+        for pat in &arm.pats {
+            self.visit_pat(pat);
+        }
+
+        if let Some(ref guard) = arm.guard {
+            self.in_unsupported_position("a match guard", |this| this.visit_expr(guard));
+        }
+
+        self.visit_expr(&arm.body);
+    }
+
+    fn visit_item(&mut self, _item: &ast::Item) { }
+
+    fn visit_mac(&mut self, mac: &ast::Mac) {
+        let name = mac.node.path.segments.last().unwrap().identifier;
+
+        if is_transition_path(&mac.node.path, self.yield_macro) {
+            if let Some(position) = self.unsupported_position {
+                self.cx.span_err(
+                    mac.span,
+                    &format!("`{}!` cannot be used in {}", name, position));
+
+                self.has_errors = true;
             }
-            ExprKind::Break(_, _) if self.inside_loop => {
-                self.contains_transition = true;
+        } else if is_path(&mac.node.path, "matches") {
+            // `matches!` is lowered into a `match`, so its guard is a match guard too.
+            let (expr, pats, guard) = parse_mac_matches(self.cx, mac);
+
+            self.visit_expr(&expr);
+
+            for pat in &pats {
+                self.visit_pat(pat);
             }
-            ExprKind::Continue(_) if self.inside_loop => {
-                self.contains_transition = true;
+
+            if let Some(ref guard) = guard {
+                self.in_unsupported_position("a match guard", |this| this.visit_expr(guard));
             }
-            ExprKind::Mac(ref mac) if mac.contains_transition(self.inside_loop) => {
-                self.contains_transition = true;
+        } else if !is_lowered_path(&mac.node.path) {
+            // We can't see how other macros use their arguments, like `println!` which expands
+            // into format arguments, so we can't lower transitions inside of them.
+            if let Some(transition) = find_transition_in_tts(&mac.node.tts, self.yield_macro) {
+                self.cx.span_err(
+                    mac.span,
+                    &format!("`{}!` cannot be used inside `{}!`", transition, name));
+
+                self.has_errors = true;
             }
-            _ => {
-                visit::walk_expr(self, expr)
+        }
+    }
+}
+
+struct ClosureTransitionVisitor<'a, 'b: 'a> {
+    cx: &'a ExtCtxt<'b>,
+    yield_macro: &'a str,
+    has_errors: bool,
+}
+
+impl<'a, 'b: 'a> visit::Visitor for ClosureTransitionVisitor<'a, 'b> {
+    fn visit_item(&mut self, _item: &ast::Item) { }
+
+    fn visit_mac(&mut self, mac: &ast::Mac) {
+        let transition = if is_transition_path(&mac.node.path, self.yield_macro) {
+            Some(mac.node.path.segments.last().unwrap().identifier)
+        } else {
+            find_transition_in_tts(&mac.node.tts, self.yield_macro)
+        };
+
+        if let Some(transition) = transition {
+            self.cx.struct_span_err(
+                mac.span,
+                &format!("`{}!` cannot be used inside a closure", transition))
+                .help("closures can't suspend the state machine they're defined in; use a `for` \
+                       loop instead of an iterator adapter like `map`")
+                .emit();
+
+            self.has_errors = true;
+        }
+    }
+}
+
+/// Macros that we parse and lower ourselves, so they may contain transitions.
+fn is_lowered_path(path: &ast::Path) -> bool {
+    is_path(path, "moved") ||
+        is_path(path, "copied") ||
+        is_path(path, "try") ||
+        is_path(path, "matches") ||
+        is_path(path, "weak")
+}
+
+/// Find a transition macro call, like `yield_!`, in the arguments of a macro we don't lower.
+fn find_transition_in_tts(tts: &[TokenTree], yield_macro: &str) -> Option<ast::Ident> {
+    for (index, tt) in tts.iter().enumerate() {
+        match *tt {
+            TokenTree::Token(_, Token::Ident(ident)) => {
+                let is_call = match tts.get(index + 1) {
+                    Some(&TokenTree::Token(_, Token::Not)) => true,
+                    _ => false,
+                };
+
+                if is_call && is_transition_name(&ident.name.as_str(), yield_macro) {
+                    return Some(ident);
+                }
+            }
+            TokenTree::Token(..) => {}
+            TokenTree::Delimited(_, ref delimited) => {
+                if let Some(ident) = find_transition_in_tts(&delimited.tts, yield_macro) {
+                    return Some(ident);
+                }
+            }
+            TokenTree::Sequence(_, ref sequence) => {
+                if let Some(ident) = find_transition_in_tts(&sequence.tts, yield_macro) {
+                    return Some(ident);
+                }
             }
         }
     }
 
-    fn visit_mac(&mut self, _mac: &ast::Mac) { }
+    None
+}
+
+fn is_transition_name(name: &str, yield_macro: &str) -> bool {
+    name == yield_macro || name == "yield_from" || name == "await" || name == "suspend"
 }
-*/
 
 pub enum Transition {
     Yield(P<ast::Expr>),
+    YieldFrom(P<ast::Expr>),
     Await(P<ast::Expr>),
     Suspend(P<ast::Expr>),
 }
 
-pub fn parse_mac_transition(cx: &ExtCtxt, mac: &ast::Mac) -> Option<Transition> {
-    if is_yield_path(&mac.node.path) {
+pub fn parse_mac_transition(cx: &ExtCtxt,
+                            yield_macro: &str,
+                            mac: &ast::Mac) -> Option<Transition> {
+    if is_yield_path(&mac.node.path, yield_macro) {
         Some(Transition::Yield(parse_mac(cx, mac)))
+    } else if is_yield_from_path(&mac.node.path) {
+        Some(Transition::YieldFrom(parse_mac(cx, mac)))
     } else if is_await_path(&mac.node.path) {
         Some(Transition::Await(parse_mac(cx, mac)))
     } else if is_suspend_path(&mac.node.path) {
@@ -130,21 +265,20 @@ pub fn parse_mac_transition(cx: &ExtCtxt, mac: &ast::Mac) -> Option<Transition>
     }
 }
 
-/*
-fn is_transition_path(path: &ast::Path) -> bool {
-    if path.global {
-        return false;
-    }
-
-    is_yield_path(path) ||
+fn is_transition_path(path: &ast::Path, yield_macro: &str) -> bool {
+    is_yield_path(path, yield_macro) ||
+        is_yield_from_path(path) ||
         is_await_path(path) ||
-        is_suspend_path(path) ||
-        is_moved_path(path)
+        is_suspend_path(path)
+}
+
+/// The yield macro is `yield_!` unless it was renamed with the `yield_macro` option.
+fn is_yield_path(path: &ast::Path, yield_macro: &str) -> bool {
+    is_path(path, yield_macro)
 }
-*/
 
-fn is_yield_path(path: &ast::Path) -> bool {
-    is_path(path, "yield_")
+fn is_yield_from_path(path: &ast::Path) -> bool {
+    is_path(path, "yield_from")
 }
 
 fn is_await_path(path: &ast::Path) -> bool {
@@ -155,8 +289,3 @@ fn is_suspend_path(path: &ast::Path) -> bool {
     is_path(path, "suspend")
 }
 
-/*
-fn is_moved_path(path: &ast::Path) -> bool {
-    is_path(path, "moved")
-}
-*/
@@ -11,9 +11,11 @@ extern crate rustc_errors as errors;
 
 #[macro_use] mod macros;
 
+mod analysis;
 mod build;
 mod data_structures;
 mod mir;
+mod options;
 mod pretty;
 mod transform;
 mod translate;
@@ -27,6 +29,7 @@ use syntax::ext::base::{
     ExtCtxt,
     MultiModifier,
 };
+use syntax::ptr::P;
 use mir::{FunctionDecl, StateMachineKind};
 
 fn expand_state_machine<'a, 'ecx>(cx: &'a ExtCtxt<'ecx>,
@@ -34,50 +37,93 @@ fn expand_state_machine<'a, 'ecx>(cx: &'a ExtCtxt<'ecx>,
                                   meta_item: &ast::MetaItem,
                                   annotatable: Annotatable,
                                   name: &str,
-                                  state_machine_kind: StateMachineKind) -> Annotatable {
-    let item = match annotatable {
-        Annotatable::Item(item) => item,
-        _ => {
-            cx.span_err(
-                meta_item.span,
-                &format!("`{}` may only be applied to functions", name));
-
-            return annotatable;
+                                  state_machine_kind: StateMachineKind) -> Vec<Annotatable> {
+    let fn_decl_ast_block = match annotatable {
+        Annotatable::Item(ref item) => {
+            match item.node {
+                ast::ItemKind::Fn(ref fn_decl, ref unsafety, _, ref abi, ref generics, ref block) => {
+                    let fn_decl = FunctionDecl::new(
+                        item.ident,
+                        fn_decl.clone(),
+                        *unsafety,
+                        *abi,
+                        generics.clone(),
+                    );
+                    Some((fn_decl, block.clone(), item.span))
+                }
+                _ => None,
+            }
         }
-    };
-
-    let fn_decl_ast_block = match item.node {
-        ast::ItemKind::Fn(ref fn_decl, ref unsafety, _, ref abi, ref generics, ref block) => {
-            let fn_decl = FunctionDecl::new(
-                item.ident,
-                fn_decl.clone(),
-                *unsafety,
-                *abi,
-                generics.clone(),
-            );
-            Some((fn_decl, block.clone()))
+        Annotatable::ImplItem(ref impl_item) => {
+            match impl_item.node {
+                ast::ImplItemKind::Method(ref sig, ref block) => {
+                    let fn_decl = FunctionDecl::new(
+                        impl_item.ident,
+                        sig.decl.clone(),
+                        sig.unsafety,
+                        sig.abi,
+                        sig.generics.clone(),
+                    );
+                    Some((fn_decl, block.clone(), impl_item.span))
+                }
+                _ => None,
+            }
+        }
+        Annotatable::TraitItem(ref trait_item) => {
+            // Only default methods have a body to turn into a state machine.
+            match trait_item.node {
+                ast::TraitItemKind::Method(ref sig, Some(ref block)) => {
+                    let fn_decl = FunctionDecl::new(
+                        trait_item.ident,
+                        sig.decl.clone(),
+                        sig.unsafety,
+                        sig.abi,
+                        sig.generics.clone(),
+                    );
+                    Some((fn_decl, block.clone(), trait_item.span))
+                }
+                _ => None,
+            }
         }
-
-        _ => None,
     };
 
-    let (fn_decl, ast_block) = match fn_decl_ast_block {
+    let (fn_decl, ast_block, span) = match fn_decl_ast_block {
         Some(data) => data,
         None => {
             cx.span_err(
-                item.span,
-                &format!("`{}` may only be applied to functions", state_machine_kind));
+                meta_item.span,
+                &format!("`{}` may only be applied to functions", name));
 
-            return Annotatable::Item(item);
+            return vec![annotatable];
         }
     };
 
+    // Without a return type we'd silently produce an iterator or future of `()`, which is never
+    // what was meant, so ask for the type to be written out.
+    if let ast::FunctionRetTy::Default(ret_span) = fn_decl.fn_decl.output {
+        let example = match state_machine_kind {
+            StateMachineKind::Generator => "-> Box<Iterator<Item=T>>",
+            StateMachineKind::Async => "-> Box<Future<Item=T, Error=E>>",
+        };
+
+        cx.struct_span_err(
+            ret_span,
+            &format!("`{}` functions must specify the `Item` type in their return type", name))
+            .help(&format!("add a return type like `{}`", example))
+            .emit();
+
+        return vec![annotatable];
+    }
+
+    let options = options::Options::parse(cx, name, meta_item);
+
     let tcx = ty::TyCtxt::new(cx);
 
     let mut mir = build::construct_fn(
         cx,
         state_machine_kind,
-        item.span,
+        options,
+        span,
         fn_decl,
         ast_block);
 
@@ -87,13 +133,122 @@ fn expand_state_machine<'a, 'ecx>(cx: &'a ExtCtxt<'ecx>,
     passes.push_pass(Box::new(transform::simplify_cfg::SimplifyCfg::new()));
     passes.run_passes(tcx, &mut mir);
 
+    if mir.options.warn_unused_captures && !is_allowed(annotatable.attrs(), "unused_variables") {
+        analysis::unused_captures::warn_unused_captures(cx, &mir);
+    }
+
     match translate::translate(cx, &mir) {
-        Some(item) => {
-            Annotatable::Item(item)
+        Some(items) => {
+            let items = keep_original_docs(annotatable.attrs(), items);
+
+            match annotatable {
+                Annotatable::ImplItem(ref impl_item) => {
+                    items.into_iter()
+                        .map(|item| Annotatable::ImplItem(into_impl_item(impl_item, item)))
+                        .collect()
+                }
+                Annotatable::TraitItem(_) => {
+                    items.into_iter()
+                        .map(|item| Annotatable::TraitItem(into_trait_item(item)))
+                        .collect()
+                }
+                _ => {
+                    items.into_iter().map(Annotatable::Item).collect()
+                }
+            }
         }
         None => {
             // We had an error, so just return the input item for a lack of a better option.
-            Annotatable::Item(item.clone())
+            vec![annotatable]
+        }
+    }
+}
+
+/// The generated function is documented as a state machine, but if the original function had its
+/// own documentation, keep that instead.
+fn keep_original_docs(attrs: &[ast::Attribute],
+                      mut items: Vec<P<ast::Item>>) -> Vec<P<ast::Item>> {
+    let docs = attrs.iter()
+        .filter(|attr| attr.check_name("doc"))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    if !docs.is_empty() {
+        let item = items.remove(0).map(|mut item| {
+            item.attrs.retain(|attr| !attr.check_name("doc"));
+            item.attrs.extend(docs);
+            item
+        });
+
+        items.insert(0, item);
+    }
+
+    items
+}
+
+/// Whether the original function has `#[allow($lint)]`.
+fn is_allowed(attrs: &[ast::Attribute], lint: &str) -> bool {
+    attrs.iter()
+        .filter(|attr| attr.check_name("allow"))
+        .filter_map(|attr| attr.meta_item_list())
+        .flat_map(|items| items)
+        .any(|item| item.check_name(lint))
+}
+
+/// Turn a translated function into a method that lives in the same `impl` as the original method.
+fn into_impl_item(impl_item: &ast::ImplItem, item: P<ast::Item>) -> P<ast::ImplItem> {
+    let item = item.unwrap();
+
+    match item.node {
+        ast::ItemKind::Fn(decl, unsafety, constness, abi, generics, block) => {
+            let sig = ast::MethodSig {
+                unsafety: unsafety,
+                constness: constness,
+                abi: abi,
+                decl: decl,
+                generics: generics,
+            };
+
+            P(ast::ImplItem {
+                id: ast::DUMMY_NODE_ID,
+                ident: item.ident,
+                vis: impl_item.vis.clone(),
+                defaultness: impl_item.defaultness,
+                attrs: item.attrs,
+                node: ast::ImplItemKind::Method(sig, block),
+                span: item.span,
+            })
+        }
+        _ => {
+            panic!("translate should only produce functions: {:?}", item);
+        }
+    }
+}
+
+/// Turn a translated function into a default method of the same trait as the original method.
+fn into_trait_item(item: P<ast::Item>) -> P<ast::TraitItem> {
+    let item = item.unwrap();
+
+    match item.node {
+        ast::ItemKind::Fn(decl, unsafety, constness, abi, generics, block) => {
+            let sig = ast::MethodSig {
+                unsafety: unsafety,
+                constness: constness,
+                abi: abi,
+                decl: decl,
+                generics: generics,
+            };
+
+            P(ast::TraitItem {
+                id: ast::DUMMY_NODE_ID,
+                ident: item.ident,
+                attrs: item.attrs,
+                node: ast::TraitItemKind::Method(sig, Some(block)),
+                span: item.span,
+            })
+        }
+        _ => {
+            panic!("translate should only produce functions: {:?}", item);
         }
     }
 }
@@ -101,7 +256,7 @@ fn expand_state_machine<'a, 'ecx>(cx: &'a ExtCtxt<'ecx>,
 fn expand_generator(cx: &mut ExtCtxt,
                     span: Span,
                     meta_item: &ast::MetaItem,
-                    annotatable: Annotatable) -> Annotatable {
+                    annotatable: Annotatable) -> Vec<Annotatable> {
     expand_state_machine(
         cx,
         span,
@@ -114,7 +269,7 @@ fn expand_generator(cx: &mut ExtCtxt,
 fn expand_async(cx: &mut ExtCtxt,
                 span: Span,
                 meta_item: &ast::MetaItem,
-                annotatable: Annotatable) -> Annotatable {
+                annotatable: Annotatable) -> Vec<Annotatable> {
     expand_state_machine(
         cx,
         span,
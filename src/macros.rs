@@ -24,3 +24,15 @@ macro_rules! span_bug {
             &format!("{}:{}: {}", file!(), line!(), format_args!($($message)*)))
     })
 }
+
+/// Log a step of the lowering process to stderr. This is compiled out unless the `trace` feature
+/// is enabled.
+#[macro_export]
+macro_rules! lowering_trace {
+    ($($message:tt)*) => ({
+        if cfg!(feature = "trace") {
+            use std::io::Write;
+            let _ = writeln!(::std::io::stderr(), "stateful: {}", format_args!($($message)*));
+        }
+    })
+}
@@ -1,5 +1,6 @@
 use aster::AstBuilder;
 use data_structures::indexed_vec::{Idx, IndexVec};
+use options::Options;
 use std::borrow::Cow;
 use std::collections::BTreeMap;
 use std::fmt::{self, Debug, Formatter, Write};
@@ -7,7 +8,7 @@ use std::ops::{Index, IndexMut};
 use std::u32;
 use syntax::abi;
 use syntax::ast;
-use syntax::codemap::Span;
+use syntax::codemap::{Span, Spanned};
 use syntax::print::pprust;
 use syntax::ptr::P;
 
@@ -36,6 +37,12 @@ macro_rules! newtype_index {
     )
 }
 
+/// The name a method's `self` is bound to inside the state machine.
+pub const SELF_IDENT: &'static str = "__stateful_self";
+
+/// The name of the argument holding the context, when the state machine has a `context` option.
+pub const CONTEXT_IDENT: &'static str = "__stateful_context";
+
 #[derive(Debug)]
 pub struct FunctionDecl {
     pub ident: ast::Ident,
@@ -43,6 +50,10 @@ pub struct FunctionDecl {
     pub unsafety: ast::Unsafety,
     pub abi: abi::Abi,
     pub generics: ast::Generics,
+
+    /// The `self` argument of a method as written in its signature. Inside the state machine it's
+    /// bound as `__stateful_self` instead, since `self` can't be bound by a state's pattern.
+    pub self_arg: Option<ast::Arg>,
 }
 
 impl FunctionDecl {
@@ -57,9 +68,15 @@ impl FunctionDecl {
             unsafety: unsafety,
             abi: abi,
             generics: generics,
+            self_arg: None,
         }
     }
 
+    pub fn with_self_arg(mut self, self_arg: Option<ast::Arg>) -> Self {
+        self.self_arg = self_arg;
+        self
+    }
+
     pub fn ident(&self) -> ast::Ident {
         self.ident
     }
@@ -68,6 +85,18 @@ impl FunctionDecl {
         &self.fn_decl.inputs
     }
 
+    /// The arguments of the generated function, which has the original `self` argument.
+    pub fn signature_inputs(&self) -> Vec<ast::Arg> {
+        match self.self_arg {
+            Some(ref self_arg) => {
+                Some(self_arg.clone()).into_iter()
+                    .chain(self.inputs()[1..].iter().cloned())
+                    .collect()
+            }
+            None => self.inputs().to_vec(),
+        }
+    }
+
     /*
     pub fn fn_decl(&self) -> &P<ast::FnDecl> {
         &self.fn_decl
@@ -95,6 +124,57 @@ impl FunctionDecl {
             }
         }
     }
+
+    /// Extract the `Item` binding from a return type like `Box<Iterator<Item=T>>` or
+    /// `impl Iterator<Item=T>`.
+    pub fn item_ty(&self) -> Option<P<ast::Ty>> {
+        match self.fn_decl.output {
+            ast::FunctionRetTy::Ty(ref ty) => find_item_ty(ty),
+            ast::FunctionRetTy::Default(_) => None,
+        }
+    }
+}
+
+fn find_item_ty(ty: &ast::Ty) -> Option<P<ast::Ty>> {
+    match ty.node {
+        ast::TyKind::Path(None, ref path) => find_item_ty_in_path(path),
+        ast::TyKind::ObjectSum(ref ty, _) |
+        ast::TyKind::Paren(ref ty) => find_item_ty(ty),
+        ast::TyKind::ImplTrait(ref bounds) => {
+            bounds.iter()
+                .filter_map(|bound| {
+                    match *bound {
+                        ast::TyParamBound::TraitTyParamBound(ref poly_trait_ref, _) => {
+                            find_item_ty_in_path(&poly_trait_ref.trait_ref.path)
+                        }
+                        ast::TyParamBound::RegionTyParamBound(_) => None,
+                    }
+                })
+                .next()
+        }
+        _ => None,
+    }
+}
+
+fn find_item_ty_in_path(path: &ast::Path) -> Option<P<ast::Ty>> {
+    let segment = match path.segments.last() {
+        Some(segment) => segment,
+        None => { return None; }
+    };
+
+    match segment.parameters {
+        ast::PathParameters::AngleBracketed(ref data) => {
+            for binding in data.bindings.iter() {
+                if &*binding.ident.name.as_str() == "Item" {
+                    return Some(binding.ty.clone());
+                }
+            }
+
+            // Look through wrappers like `Box<...>`.
+            data.types.iter().filter_map(|ty| find_item_ty(ty)).next()
+        }
+        ast::PathParameters::Parenthesized(_) => None,
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -117,6 +197,9 @@ impl fmt::Display for StateMachineKind {
 pub struct Mir {
     pub state_machine_kind: StateMachineKind,
 
+    /// The options passed to the state machine attribute.
+    pub options: Options,
+
     /// List of basic blocks. References to basic block use a newtyped index type `BasicBlock`
     /// that indexes into this vector.
     basic_blocks: IndexVec<BasicBlock, BasicBlockData>,
@@ -132,6 +215,10 @@ pub struct Mir {
     /// variables and temporaries.
     pub local_decls: IndexVec<Local, LocalDecl>,
 
+    /// Items declared inside the function body. These don't participate in the state machine,
+    /// so they're hoisted as-is into the generated function.
+    pub items: Vec<P<ast::Item>>,
+
     /// A span representing this MIR, for error reporting
     pub span: Span,
 
@@ -144,9 +231,11 @@ pub const START_BLOCK: BasicBlock = BasicBlock(0);
 impl Mir {
     pub fn new(
         state_machine_kind: StateMachineKind,
+        options: Options,
         basic_blocks: IndexVec<BasicBlock, BasicBlockData>,
         visibility_scopes: IndexVec<VisibilityScope, VisibilityScopeData>,
         local_decls: IndexVec<Local, LocalDecl>,
+        items: Vec<P<ast::Item>>,
         span: Span,
         fn_decl: FunctionDecl) -> Self
     {
@@ -159,9 +248,11 @@ impl Mir {
 
         Mir {
             state_machine_kind: state_machine_kind,
+            options: options,
             basic_blocks: basic_blocks,
             visibility_scopes: visibility_scopes,
             local_decls: local_decls,
+            items: items,
             span: span,
             fn_decl: fn_decl,
         }
@@ -254,6 +345,10 @@ pub struct LocalDecl {
     pub source_info: SourceInfo,
 
     pub shadowed_decl: Option<Local>,
+
+    /// The initializer of a local declared with `let x = recompute!(...)`. The local isn't stored
+    /// in the state, and this is evaluated again whenever a state it's live in is entered.
+    pub recompute: Option<P<ast::Expr>>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -365,6 +460,10 @@ pub enum TerminatorKind {
     /// `END_BLOCK`.
     Return,
 
+    /// Indicates a block that can't be reached at runtime, like the code after a call that
+    /// diverges.
+    Unreachable,
+
     /// jump to target on next iteration.
     Suspend {
         // FIXME: We don't yet support resuming the coroutine with a value yet.
@@ -419,7 +518,8 @@ impl TerminatorKind {
                 targets.iter().map(|arm| arm.block).collect()
             }
             TerminatorKind::If { targets: (then, else_), .. } => vec![then, else_],
-            TerminatorKind::Return => vec![],
+            TerminatorKind::Return |
+            TerminatorKind::Unreachable => vec![],
             TerminatorKind::Suspend { target, .. } => vec![target],
         }
     }
@@ -433,7 +533,8 @@ impl TerminatorKind {
             TerminatorKind::If { targets: (ref mut then, ref mut else_), .. } => {
                 vec![then, else_]
             }
-            TerminatorKind::Return => vec![],
+            TerminatorKind::Return |
+            TerminatorKind::Unreachable => vec![],
             TerminatorKind::Suspend { ref mut target, .. } => vec![target],
         }
     }
@@ -448,6 +549,7 @@ impl TerminatorKind {
             If { cond: ref lv, .. } => write!(fmt, "if({:?})", lv),
             Match { discr: ref lv, .. } => write!(fmt, "match({:?})", lv),
             Return => write!(fmt, "return"),
+            Unreachable => write!(fmt, "unreachable"),
             Suspend { ref rvalue, .. } => write!(fmt, "suspend({:?})", rvalue),
         }
     }
@@ -456,7 +558,7 @@ impl TerminatorKind {
     pub fn fmt_successor_labels(&self) -> Vec<Cow<'static, str>> {
         use self::TerminatorKind::*;
         match *self {
-            Return => vec![],
+            Return | Unreachable => vec![],
             Goto { .. } => vec!["".into()],
             If { .. } => vec!["true".into(), "false".into()],
             Match { ref targets, .. } => {
@@ -511,8 +613,9 @@ pub struct Projection<B, V> {
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum ProjectionElem<V> {
     Deref,
-
-    //Field(Field, Ty<'tcx>),
+    Field(ast::SpannedIdent),
+    /// A field accessed by its index, like `pair.0`.
+    TupleField(Spanned<usize>),
     Index(V),
 
     /*
@@ -562,6 +665,18 @@ impl Lvalue {
         self.elem(ProjectionElem::Deref)
     }
 
+    pub fn field(self, ident: ast::SpannedIdent) -> Lvalue {
+        self.elem(ProjectionElem::Field(ident))
+    }
+
+    pub fn tuple_field(self, index: Spanned<usize>) -> Lvalue {
+        self.elem(ProjectionElem::TupleField(index))
+    }
+
+    pub fn index(self, index: Operand) -> Lvalue {
+        self.elem(ProjectionElem::Index(index))
+    }
+
     pub fn elem(self, elem: LvalueElem) -> Lvalue {
         Lvalue::Projection(Box::new(LvalueProjection {
             base: self,
@@ -599,8 +714,16 @@ impl<B, V> ToExpr for Projection<B, V> where B: ToExpr, V: ToExpr {
             ProjectionElem::Deref => {
                 AstBuilder::new().span(base.span).expr().deref().build(base)
             }
+            ProjectionElem::Field(ref ident) => {
+                AstBuilder::new().span(ident.span).expr().field(ident.node).build(base)
+            }
+            ProjectionElem::TupleField(index) => {
+                AstBuilder::new().span(index.span).expr().build_expr_kind(
+                    ast::ExprKind::TupField(base, index))
+            }
             ProjectionElem::Index(ref idx) => {
-                idx.to_expr(local_decls)
+                AstBuilder::new().span(base.span).expr().build_expr_kind(
+                    ast::ExprKind::Index(base, idx.to_expr(local_decls)))
             }
         }
     }
@@ -617,6 +740,10 @@ impl Debug for Lvalue {
                 match data.elem {
                     ProjectionElem::Deref =>
                         write!(fmt, "(*{:?})", data.base),
+                    ProjectionElem::Field(ref ident) =>
+                        write!(fmt, "{:?}.{}", data.base, ident.node),
+                    ProjectionElem::TupleField(ref index) =>
+                        write!(fmt, "{:?}.{}", data.base, index.node),
                     ProjectionElem::Index(ref index) =>
                         write!(fmt, "{:?}[{:?}]", data.base, index),
                 }
@@ -684,6 +811,16 @@ pub enum Rvalue {
     Tuple(Vec<Operand>),
     Struct(ast::Path, Vec<ast::Field>, Vec<Operand>, Option<Operand>),
     Range(Option<Operand>, Option<Operand>, ast::RangeLimits),
+    /// `[value; count]`. The count is a constant expression, so it's passed through as is.
+    Repeat(Operand, P<ast::Expr>),
+    /// `value: T`. The ascribed type is kept so it still guides inference of the value.
+    Type(Operand, P<ast::Ty>),
+    /// A closure is passed through verbatim, since its body can't contain transitions. It refers
+    /// to the locals it captures by name.
+    Closure(P<ast::Expr>),
+    /// The initializer of a `recompute!` local, which is passed through verbatim so it can be
+    /// evaluated again on resumption. It reads locals by name, so it doesn't move them.
+    Recompute(P<ast::Expr>),
 }
 
 impl ToExpr for Rvalue {
@@ -745,6 +882,16 @@ impl ToExpr for Rvalue {
                     .from_opt(from)
                     .to_opt(to, *limits)
             }
+            Rvalue::Repeat(ref value, ref count) => {
+                builder.expr().build_expr_kind(
+                    ast::ExprKind::Repeat(value.to_expr(local_decls), count.clone()))
+            }
+            Rvalue::Type(ref value, ref ty) => {
+                builder.expr().build_expr_kind(
+                    ast::ExprKind::Type(value.to_expr(local_decls), ty.clone()))
+            }
+            Rvalue::Closure(ref closure) => closure.clone(),
+            Rvalue::Recompute(ref expr) => expr.clone(),
         }
     }
 }
@@ -793,9 +940,9 @@ impl Debug for Rvalue {
                 let mut first = true;
                 for (field, item) in fields.iter().zip(items.iter()) {
                     if first {
-                        write!(fmt, ", ")?;
-                    } else {
                         first = false;
+                    } else {
+                        write!(fmt, ", ")?;
                     }
                     if field.is_shorthand {
                         write!(fmt, "{:?}", item)?;
@@ -827,6 +974,18 @@ impl Debug for Rvalue {
 
                 Ok(())
             }
+            Repeat(ref value, ref count) => {
+                write!(fmt, "[{:?}; {}]", value, pprust::expr_to_string(count))
+            }
+            Type(ref value, ref ty) => {
+                write!(fmt, "{:?}: {}", value, pprust::ty_to_string(ty))
+            }
+            Closure(ref closure) => {
+                write!(fmt, "{}", pprust::expr_to_string(closure))
+            }
+            Recompute(ref expr) => {
+                write!(fmt, "recompute!({})", pprust::expr_to_string(expr))
+            }
         }
     }
 }
@@ -0,0 +1,265 @@
+use aster::AstBuilder;
+use syntax::ast;
+use syntax::ext::base::ExtCtxt;
+use syntax::parse;
+use syntax::parse::token::Token;
+use syntax::ptr::P;
+
+/// Options passed to the state machine attribute, as in `#[generator(specialize)]`.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Specialize `Iterator::count` and `Iterator::last` when the generator is pure. `count`
+    /// steps through a copy of the state machine that doesn't build the yielded values.
+    pub specialize: bool,
+
+    /// Emit a `{name}_drain_into` function that runs the generator into an `Extend` sink.
+    pub drain_into: bool,
+
+    /// Emit a `{name}_steps` function that iterates over each step of the state machine, pairing
+    /// the produced value with the name of the state it transitioned into.
+    pub expose_steps: bool,
+
+    /// Make the state machine `Clone` when all of its captured locals are `Clone`. This requires
+    /// the `closure_to_fn_coercion` feature.
+    pub clone: bool,
+
+    /// Implement `Debug` for the state machine, printing the name of its current state. This
+    /// doesn't require the captured locals to be `Debug`.
+    pub debug: bool,
+
+    /// Check that the state machine is `Send` where it's defined, rather than where it's used.
+    pub assert_send: bool,
+
+    /// Warn about locals that are captured across a suspension point but never used afterwards.
+    /// Uses are found by name, and a guard that is only held for its destructor looks unused, so
+    /// this is opt-in. `#[allow(unused_variables)]` on the function silences it.
+    pub warn_unused_captures: bool,
+
+    /// Emit a note listing the locals captured by each state, to help find the largest state.
+    pub size_report: bool,
+
+    /// Create the `Vec` and `String` accumulators declared in the body with `let acc =
+    /// Vec::new()` or `let acc = String::new()` with room for this many values up front.
+    pub reserve: Option<usize>,
+
+    /// The name of the macro that yields a value, in place of `yield_`.
+    pub yield_macro: Option<String>,
+
+    /// The type every yielded value must coerce to. Each yield is checked against it with a typed
+    /// `let`, so mismatches are reported at the yield instead of somewhere in the state machine.
+    pub item: Option<P<ast::Ty>>,
+
+    /// A function to call with a reference to every value before it is yielded.
+    pub on_yield: Option<ast::Path>,
+
+    /// A function to call every time the state machine is resumed after suspending.
+    pub on_resume: Option<ast::Path>,
+
+    /// The type of a context that's passed to the generated function as an extra last argument.
+    /// It's moved into the step function once, rather than from state to state, and each step
+    /// lends it to the body through `ctx!()`. The borrow can't be held across a transition. To
+    /// borrow the context instead, use a reference type like `&'a Ctx` and name the lifetime in
+    /// the return type, as in `Box<Iterator<Item=T> + 'a>`.
+    pub context: Option<P<ast::Ty>>,
+}
+
+impl Options {
+    pub fn parse(cx: &ExtCtxt, name: &str, meta_item: &ast::MetaItem) -> Self {
+        let mut options = Options::default();
+
+        let items = match meta_item.meta_item_list() {
+            Some(items) => items,
+            None => { return options; }
+        };
+
+        for item in items {
+            let option = match item.name() {
+                Some(option) => option,
+                None => {
+                    cx.span_err(
+                        item.span,
+                        &format!("expected an option in `#[{}(...)]`", name));
+                    continue;
+                }
+            };
+
+            match &*option.as_str() {
+                "specialize" if item.is_word() => {
+                    options.specialize = true;
+                }
+                "drain_into" if item.is_word() => {
+                    options.drain_into = true;
+                }
+                "expose_steps" if item.is_word() => {
+                    options.expose_steps = true;
+                }
+                "clone" if item.is_word() => {
+                    options.clone = true;
+                }
+                "debug" if item.is_word() => {
+                    options.debug = true;
+                }
+                "assert_send" if item.is_word() => {
+                    options.assert_send = true;
+                }
+                "warn_unused_captures" if item.is_word() => {
+                    options.warn_unused_captures = true;
+                }
+                "size_report" if item.is_word() => {
+                    options.size_report = true;
+                }
+                "reserve" => {
+                    options.reserve = parse_usize_option(cx, name, item);
+                }
+                "yield_macro" => {
+                    options.yield_macro = parse_macro_name_option(cx, name, item);
+                }
+                "item" => {
+                    options.item = parse_ty_option(cx, name, item);
+                }
+                "on_yield" => {
+                    options.on_yield = parse_path_option(cx, name, item);
+                }
+                "on_resume" => {
+                    options.on_resume = parse_path_option(cx, name, item);
+                }
+                "context" => {
+                    options.context = parse_ty_option(cx, name, item);
+                }
+                _ => {
+                    cx.span_err(
+                        item.span,
+                        &format!("unknown `{}` option `{}`", name, option));
+                }
+            }
+        }
+
+        options
+    }
+
+    /// The name of the macro that yields a value.
+    pub fn yield_macro(&self) -> &str {
+        match self.yield_macro {
+            Some(ref yield_macro) => yield_macro,
+            None => "yield_",
+        }
+    }
+}
+
+/// Parse an option like `reserve = "16"`.
+fn parse_usize_option(cx: &ExtCtxt,
+                      name: &str,
+                      item: &ast::NestedMetaItem) -> Option<usize> {
+    let value = item.value_str().and_then(|value| value.as_str().parse().ok());
+
+    if value.is_none() {
+        cx.span_err(
+            item.span,
+            &format!("expected a number in `#[{}({} = \"16\")]`",
+                     name,
+                     item.name().unwrap()));
+    }
+
+    value
+}
+
+/// Parse an option like `yield_macro = "emit"`.
+fn parse_macro_name_option(cx: &ExtCtxt,
+                           name: &str,
+                           item: &ast::NestedMetaItem) -> Option<String> {
+    let value = item.value_str().map(|value| value.as_str().to_string());
+
+    match value {
+        Some(ref value) if is_ident(value) => {}
+        _ => {
+            cx.span_err(
+                item.span,
+                &format!("expected a macro name in `#[{}({} = \"name\")]`",
+                         name,
+                         item.name().unwrap()));
+            return None;
+        }
+    }
+
+    value
+}
+
+fn is_ident(value: &str) -> bool {
+    let mut chars = value.chars();
+
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+/// Parse an option like `item = "Option<u32>"`.
+fn parse_ty_option(cx: &ExtCtxt,
+                   name: &str,
+                   item: &ast::NestedMetaItem) -> Option<P<ast::Ty>> {
+    let value = match item.value_str() {
+        Some(value) => value.as_str().to_string(),
+        None => {
+            cx.span_err(
+                item.span,
+                &format!("expected a type in `#[{}({} = \"Type\")]`",
+                         name,
+                         item.name().unwrap()));
+            return None;
+        }
+    };
+
+    let mut parser = parse::new_parser_from_source_str(
+        cx.parse_sess,
+        format!("<{} option>", item.name().unwrap()),
+        value.clone());
+
+    match parser.parse_ty() {
+        Ok(ref ty) if parser.token == Token::Eof => Some(ty.clone()),
+        result => {
+            if let Err(mut err) = result {
+                err.cancel();
+            }
+
+            cx.span_err(
+                item.span,
+                &format!("`{}` is not a valid type", value));
+            None
+        }
+    }
+}
+
+/// Parse an option like `on_yield = "path::to::function"`.
+fn parse_path_option(cx: &ExtCtxt,
+                     name: &str,
+                     item: &ast::NestedMetaItem) -> Option<ast::Path> {
+    let value = match item.value_str() {
+        Some(value) => value,
+        None => {
+            cx.span_err(
+                item.span,
+                &format!("expected a path in `#[{}({} = \"path::to::function\")]`",
+                         name,
+                         item.name().unwrap()));
+            return None;
+        }
+    };
+
+    let value = value.as_str();
+
+    let (global, value) = if value.starts_with("::") {
+        (true, &value[2..])
+    } else {
+        (false, &value[..])
+    };
+
+    let mut path = AstBuilder::new().span(item.span).path();
+
+    if global {
+        path = path.global();
+    }
+
+    Some(path.ids(value.split("::")).build())
+}
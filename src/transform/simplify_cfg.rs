@@ -12,6 +12,7 @@ use bit_vec::BitVec;
 use data_structures::indexed_vec::{Idx, IndexVec};
 use mir::*;
 use std::borrow::Cow;
+use std::collections::HashSet;
 use super::{MirPass, Pass};
 use traversal;
 use ty::TyCtxt;
@@ -115,29 +116,34 @@ impl<'a> CfgSimplifier<'a> {
 
     // Collapse a goto chain starting from `start`
     fn collapse_goto_chain(&mut self, start: &mut BasicBlock, changed: &mut bool) {
-        let mut terminator = match self.basic_blocks[*start] {
+        let collapsible = match self.basic_blocks[*start] {
             BasicBlockData {
                 ref statements,
-                terminator: ref mut terminator @ Some(Terminator {
-                    // FIXME: We cannot currently optimize end scopes or else we might not properly
-                    // drop copyable types.
-                    kind: TerminatorKind::Goto {
-                        end_scope: false,
-                        ..
-                    },
+                terminator: Some(Terminator {
+                    kind: TerminatorKind::Goto { end_scope, .. },
                     ..
                 }), ..
-            } if statements.is_empty() => terminator.take(),
+            } => {
+                // An empty block that ends a scope can only be skipped if nothing goes out of
+                // scope.
+                statements.is_empty() && (!end_scope || self.is_drop_block(*start))
+            }
             // if `terminator` is None, this means we are in a loop. In that
             // case, let all the loop collapse to its entry.
-            _ => return
+            _ => false
         };
 
+        if !collapsible {
+            return
+        }
+
+        let mut terminator = self.basic_blocks[*start].terminator.take();
+
         let target = match terminator {
             Some(Terminator {
                 kind: TerminatorKind::Goto {
                     ref mut target,
-                    end_scope: false,
+                    ..
                 },
                 ..
             }) => {
@@ -163,10 +169,11 @@ impl<'a> CfgSimplifier<'a> {
                        -> bool
     {
         let target = match terminator.kind {
-            // FIXME: We cannot currently optimize end scopes or else we might not properly drop
-            // copyable types.
-            TerminatorKind::Goto { target, end_scope: false }
-                if self.pred_count[target] == 1
+            // FIXME: We cannot currently optimize end scopes in general or else we might not
+            // properly drop copyable types. Drop blocks are fine, since they drop everything
+            // that goes out of scope themselves.
+            TerminatorKind::Goto { target, end_scope }
+                if self.pred_count[target] == 1 && (!end_scope || self.is_drop_block(target))
                 => target,
             _ => return false
         };
@@ -186,6 +193,30 @@ impl<'a> CfgSimplifier<'a> {
         true
     }
 
+    // Whether `block` only drops the locals that go out of scope when it jumps to its target,
+    // like the blocks `exit_scope` creates.
+    fn is_drop_block(&self, block: BasicBlock) -> bool {
+        let data = &self.basic_blocks[block];
+
+        let target = match data.terminator {
+            Some(Terminator { kind: TerminatorKind::Goto { target, .. }, .. }) => target,
+            _ => return false
+        };
+
+        let mut dropped = HashSet::new();
+        for statement in &data.statements {
+            match statement.kind {
+                StatementKind::Drop { lvalue, .. } => { dropped.insert(lvalue); }
+                _ => return false
+            }
+        }
+
+        let outgoing = live_locals(&self.basic_blocks[target].incoming_decls);
+
+        live_locals(&data.incoming_decls).iter()
+            .all(|local| outgoing.contains(local) || dropped.contains(local))
+    }
+
     // turn a branch with all successors identical to a goto
     fn simplify_branch(&mut self, terminator: &mut Terminator) -> bool {
         match terminator.kind {
@@ -217,6 +248,12 @@ impl<'a> CfgSimplifier<'a> {
     }
 }
 
+fn live_locals(decls: &LiveDeclMap) -> HashSet<Local> {
+    decls.values()
+        .flat_map(|decls| decls.iter().map(|decl| decl.local()))
+        .collect()
+}
+
 fn remove_dead_blocks(mir: &mut Mir) {
     let mut seen = BitVec::from_elem(mir.basic_blocks().len(), false);
     for (bb, _) in traversal::preorder(mir) {
@@ -11,7 +11,7 @@
 //! This pass just dumps MIR at a specified point.
 
 use data_structures::indexed_vec::Idx;
-use mir::Mir;
+use mir::{Mir, START_BLOCK, TerminatorKind};
 use std::borrow::Cow;
 use super::{MirPassHook, Pass};
 use ty::TyCtxt;
@@ -27,8 +27,44 @@ impl MirPassHook for Validate {
         _is_after: bool)
     {
         let basic_blocks = mir.basic_blocks();
+
+        // `translate` starts the state machine in the state of `START_BLOCK`, so a pass that
+        // renumbers blocks must keep it first.
+        if basic_blocks.len() <= START_BLOCK.index() {
+            span_bug!(&tcx, mir.span, "the start block {:?} does not exist", START_BLOCK);
+        }
+
+        let mut return_block = None;
+
         for (bb, block) in basic_blocks.iter_enumerated() {
-            let terminator = block.terminator();
+            // Catch a lowering that forgot to terminate a block here, rather than with a panic
+            // somewhere in `translate`.
+            let terminator = match block.terminator {
+                Some(ref terminator) => terminator,
+                None => {
+                    span_bug!(
+                        &tcx,
+                        block.span,
+                        "block {:?} ({}) has no terminator",
+                        bb,
+                        block.name.unwrap_or("<unnamed>"));
+                }
+            };
+
+            // Every `return` is lowered into a jump to the single return block, so another block
+            // that returns means a pass duplicated or misnumbered it.
+            if let TerminatorKind::Return = terminator.kind {
+                if let Some(return_block) = return_block {
+                    span_bug!(
+                        &tcx,
+                        block.span,
+                        "block {:?} returns, but so does the return block {:?}",
+                        bb,
+                        return_block);
+                }
+
+                return_block = Some(bb);
+            }
 
             for succ in terminator.successors() {
                 if succ.index() >= basic_blocks.len() {
@@ -96,7 +96,10 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                         ]
                     }
                     StateMachineKind::Async => {
-                        let return_expr = ast_builder.expr().id("return_");
+                        // The function's tail expression and any `return`s were assigned into the
+                        // return pointer, so it holds the future's completion value.
+                        let return_expr = ast_builder.expr()
+                            .id(self.mir.local_decls[RETURN_POINTER].name);
                         let ready_expr = ast_builder.expr().call()
                             .path()
                                 .global()
@@ -114,13 +117,44 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                     }
                 }
             }
+            TerminatorKind::Unreachable => {
+                vec![
+                    ast_builder.stmt().semi().build(quote_expr!(self.cx, unreachable!()))
+                ]
+            }
             TerminatorKind::Suspend { ref rvalue, target } => {
                 let rvalue = rvalue.to_expr(&self.mir.local_decls);
                 let ast_builder = ast_builder.span(rvalue.span);
                 let next_state = self.state_expr(terminator.source_info.span, target);
 
                 match self.mir.state_machine_kind {
+                    StateMachineKind::Generator if self.skip_items => {
+                        // The value is never looked at, so don't build it. Building it may move
+                        // locals though, so keep those moves in a closure that's never called.
+                        let tuple = quote_expr!(self.cx, {
+                            let _ = || $rvalue;
+                            (::std::option::Option::Some(()), $next_state)
+                        });
+
+                        vec![
+                            ast_builder.stmt().semi().return_expr()
+                                .build(tuple)
+                        ]
+                    }
                     StateMachineKind::Generator => {
+                        let rvalue = match self.mir.options.on_yield {
+                            Some(ref on_yield) => {
+                                quote_expr!(self.cx, {
+                                    let value = $rvalue;
+                                    if let ::std::option::Option::Some(ref value) = value {
+                                        $on_yield(value);
+                                    }
+                                    value
+                                })
+                            }
+                            None => rvalue,
+                        };
+
                         let tuple = ast_builder.expr().tuple()
                             .expr().build(rvalue)
                             .expr().build(next_state)
@@ -1,69 +1,102 @@
+use analysis;
 use aster::AstBuilder;
 use data_structures::indexed_vec::Idx;
 use mir::*;
+use std::collections::HashSet;
 use syntax::ast;
 use syntax::ext::base::ExtCtxt;
 use syntax::fold;
 use syntax::ptr::P;
 
-pub fn translate(cx: &ExtCtxt, mir: &Mir) -> Option<P<ast::Item>> {
+pub fn translate(cx: &ExtCtxt, mir: &Mir) -> Option<Vec<P<ast::Item>>> {
     let ast_builder = AstBuilder::new().span(mir.span);
 
     let return_ty = mir.fn_decl.return_ty();
 
-    let item_builder = ast_builder.item().fn_(mir.fn_decl.ident())
-        .with_args(mir.fn_decl.inputs().iter().cloned())
+    // If the original function was documented, its documentation replaces this.
+    let doc = format!("Generated by `#[{}]` as a state machine with {} states.",
+                      mir.state_machine_kind,
+                      mir.basic_blocks().len());
+
+    let inputs = signature_inputs(cx, mir);
+
+    let item_builder = ast_builder.item()
+        .attr().doc(&doc)
+        .fn_(mir.fn_decl.ident())
+        .with_args(inputs.clone())
         .build_return(return_ty.clone())
         .generics().with(mir.fn_decl.generics().clone())
         .build();
 
+    // Find all the blocks we enter when resuming after a suspend.
+    let resume_blocks = mir.basic_blocks().iter()
+        .filter_map(|block_data| {
+            match block_data.terminator().kind {
+                TerminatorKind::Suspend { target, .. } => Some(target),
+                _ => None,
+            }
+        })
+        .collect();
+
     let builder = Builder {
         cx: cx,
-        ast_builder: ast_builder,
+        ast_builder: ast_builder.clone(),
         mir: mir,
+        resume_blocks: resume_blocks,
+        skip_items: false,
     };
 
+    if mir.options.size_report {
+        builder.report_state_sizes();
+    }
+
     let start_state_expr = builder.state_expr(mir.span, START_BLOCK);
-    let (state_enum, state_default, state_arms) =
+    let (state_enum_item, state_default, state_arms) =
         builder.state_enum_default_and_arms();
 
     let state_machine_impl;
     let state_machine_impl_driver;
+    let mut specialize = false;
 
     match mir.state_machine_kind {
         StateMachineKind::Generator => {
-            state_machine_impl = quote_item!(cx,
-                impl<S, F, Item> StateMachine<S, F>
-                    where S: ::std::default::Default,
-                          F: Fn(S) -> (::std::option::Option<Item>, S),
-                {
-                    fn new(initial_state: S, next: F) -> Self {
-                        StateMachine {
-                            state: initial_state,
-                            next: next,
-                        }
-                    }
-                }
-            ).unwrap();
-
-            state_machine_impl_driver = quote_item!(cx,
-                impl<S, F, Item> ::std::iter::Iterator for StateMachine<S, F>
-                    where S: ::std::default::Default,
-                          F: Fn(S) -> (::std::option::Option<Item>, S)
-                {
-                    type Item = Item;
+            // Both step closures would need to own the context.
+            if mir.options.specialize && mir.options.context.is_some() {
+                cx.span_err(mir.span, "`specialize` can't be combined with `context`");
+                return None;
+            }
 
-                    fn next(&mut self) -> ::std::option::Option<Item> {
-                        let state = ::std::mem::replace(&mut self.state, S::default());
-                        let (value, state) = (self.next)(state);
-                        self.state = state;
-                        value
-                    }
+            specialize = mir.options.specialize && {
+                let pure = analysis::purity::is_pure(mir);
+                if !pure {
+                    cx.span_warn(
+                        mir.span,
+                        "generator may have side effects, so `count` and `last` will not be \
+                         specialized");
                 }
-            ).unwrap();
+                pure
+            };
+
+            state_machine_impl = builder.generator_new_impl(specialize);
+            state_machine_impl_driver = builder.iterator_impl(specialize);
         }
         StateMachineKind::Async => {
-            state_machine_impl = quote_item!(cx,
+            if mir.options.specialize {
+                cx.span_err(mir.span, "`specialize` is only supported by generators");
+                return None;
+            }
+
+            if mir.options.reserve.is_some() {
+                cx.span_err(mir.span, "`reserve` is only supported by generators");
+                return None;
+            }
+
+            if mir.options.drain_into {
+                cx.span_err(mir.span, "`drain_into` is only supported by generators");
+                return None;
+            }
+
+            state_machine_impl = builder.expect_generated(quote_item!(cx,
                 impl<S, F, Item, Error> StateMachine<S, F>
                     where S: ::std::default::Default,
                           F: Fn(S) -> ::std::result::Result<(::futures::Async<Item>, S), Error>,
@@ -75,9 +108,9 @@ pub fn translate(cx: &ExtCtxt, mir: &Mir) -> Option<P<ast::Item>> {
                         }
                     }
                 }
-            ).unwrap();
+            ), "`StateMachine` impl");
 
-            state_machine_impl_driver = quote_item!(cx,
+            state_machine_impl_driver = builder.expect_generated(quote_item!(cx,
                 impl<S, F, Item, Error> ::futures::Future for StateMachine<S, F>
                     where S: ::std::default::Default,
                           F: Fn(S) -> ::std::result::Result<(::futures::Async<Item>, S), Error>,
@@ -92,56 +125,197 @@ pub fn translate(cx: &ExtCtxt, mir: &Mir) -> Option<P<ast::Item>> {
                         Ok(value)
                     }
                 }
-            ).unwrap();
+            ), "`Future` impl");
         }
     }
 
-    let mut state_machine_closure = quote_expr!(cx,
-        StateMachine::new(
-            $start_state_expr,
-            |mut state| {
-                loop {
-                    match state {
-                        $state_arms
-                        State::Illegal => {
-                            unreachable!("illegal state")
-                        }
-                    }
-                }
+    let items = &mir.items;
+
+    // A method's `self` is stored in the state under another name.
+    let self_binding = mir.fn_decl.self_arg.as_ref().map(|_| {
+        let self_ident = ast_builder.id(SELF_IDENT);
+        builder.expect_generated(quote_stmt!(cx, let $self_ident = self;), "`self` binding")
+    });
+
+    let mut step = builder.step_closure(state_arms);
+
+    // The specialized `count` steps through a copy of the state machine that doesn't build the
+    // yielded values.
+    let mut skip_step = if specialize {
+        let skip_builder = Builder {
+            cx: cx,
+            ast_builder: ast_builder.clone(),
+            mir: mir,
+            resume_blocks: builder.resume_blocks.clone(),
+            skip_items: true,
+        };
+
+        let (_, _, skip_state_arms) = skip_builder.state_enum_default_and_arms();
+        Some(skip_builder.step_closure(skip_state_arms))
+    } else {
+        None
+    };
+
+    let state_machine_struct;
+    let state_enum;
+
+    if mir.options.clone {
+        if mir.options.context.is_some() {
+            cx.span_err(mir.span, "`clone` can't be combined with `context`");
+            return None;
+        }
+
+        // Closures can't be cloned, but the step closure never captures anything besides the
+        // state, so it can be coerced into a function pointer, which can.
+        step = quote_expr!(cx, $step as fn(_) -> _);
+        skip_step = skip_step.map(|skip_step| quote_expr!(cx, $skip_step as fn(_) -> _));
+
+        state_machine_struct = builder.expect_generated(quote_item!(cx,
+            #[derive(Clone)]
+            struct StateMachine<S, F> {
+                state: S,
+                next: F,
+            }
+        ), "`StateMachine` struct");
+
+        state_enum = state_enum_item.map(|mut item| {
+            item.attrs.push(quote_attr!(cx, #[derive(Clone)]));
+            item
+        });
+    } else {
+        state_machine_struct = builder.expect_generated(quote_item!(cx,
+            struct StateMachine<S, F> {
+                state: S,
+                next: F,
             }
-        )
+        ), "`StateMachine` struct");
+
+        state_enum = state_enum_item;
+    }
+
+    // `{name}_steps` drives the state machine itself, so it never needs the specialized one.
+    let state_machine = quote_expr!(cx,
+        StateMachine::new($start_state_expr, $step)
     );
 
+    let specialized_state_machine = match skip_step {
+        Some(skip_step) => {
+            quote_expr!(cx,
+                StateMachine::new($start_state_expr, ($step, $skip_step))
+            )
+        }
+        None => state_machine.clone(),
+    };
+
+    let state_machine_debug = if mir.options.debug {
+        let state_name_trait = builder.state_name_trait();
+        let state_name_impl = builder.state_name_impl();
+
+        let debug_impl = builder.expect_generated(quote_item!(cx,
+            impl<S, F> ::std::fmt::Debug for StateMachine<S, F>
+                where S: StateName,
+            {
+                fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                    write!(f, "StateMachine {{ state: {} }}", self.state.state_name())
+                }
+            }
+        ), "`Debug` impl");
+
+        vec![state_name_trait, state_name_impl, debug_impl]
+    } else {
+        vec![]
+    };
+
+    // The state machine's type can't be named, so check it's `Send` by passing it through a
+    // function. Any error then points at the generator's definition.
+    let (assert_send, checked_state_machine) = if mir.options.assert_send {
+        let assert_send = builder.expect_generated(quote_item!(cx,
+            fn __stateful_assert_send<T: ::std::marker::Send>(value: T) -> T { value }
+        ), "`Send` assertion");
+
+        let checked_state_machine = ast_builder.expr().call()
+            .id("__stateful_assert_send")
+            .arg().build(specialized_state_machine)
+            .build();
+
+        (Some(assert_send), checked_state_machine)
+    } else {
+        (None, specialized_state_machine)
+    };
+
     // If we're not using impl trait, we need to wrap the closure in a box.
-    match return_ty.node {
-        ast::TyKind::ImplTrait(_) => { }
+    let state_machine_closure = match return_ty.node {
+        ast::TyKind::ImplTrait(_) => checked_state_machine,
         _ => {
-            state_machine_closure = ast_builder.expr()
+            ast_builder.expr()
                 .box_()
-                .build(state_machine_closure);
+                .build(checked_state_machine)
         }
-    }
+    };
 
     let block = quote_block!(cx, {
-        struct StateMachine<S, F> {
-            state: S,
-            next: F,
-        }
-
+        $items
+        $state_machine_struct
         $state_machine_impl
         $state_machine_impl_driver
         $state_enum
         $state_default
+        $state_machine_debug
+        $assert_send
+        $self_binding
         $state_machine_closure
     });
 
-    let item = item_builder.build(block);
+    let mut items = vec![item_builder.build(block)];
+
+    if mir.options.drain_into {
+        let item = builder.drain_fn(
+            &state_machine_struct,
+            &state_enum,
+            &state_default,
+            &self_binding,
+            &state_machine);
+
+        let item = match item {
+            Some(item) => item,
+            None => { return None; }
+        };
+
+        items.push(item);
+    }
+
+    if mir.options.expose_steps {
+        let item = builder.steps_fn(
+            &state_machine_struct,
+            &builder.generator_new_impl(false),
+            &state_enum,
+            &state_default,
+            &self_binding,
+            &state_machine);
+
+        let item = match item {
+            Some(item) => item,
+            None => { return None; }
+        };
+
+        items.push(item);
+    }
 
     // Syntax extensions are not allowed to have any node ids, so we need to remove them before we
-    // return the item to the caller.
-    let item = strip_node_ids(item);
+    // return the items to the caller.
+    Some(items.into_iter().map(strip_node_ids).collect())
+}
+
+/// The arguments of the generated functions. A context is passed in after the original arguments.
+fn signature_inputs(cx: &ExtCtxt, mir: &Mir) -> Vec<ast::Arg> {
+    let mut inputs = mir.fn_decl.signature_inputs();
+
+    if let Some(ref context) = mir.options.context {
+        let context_ident = AstBuilder::new().id(CONTEXT_IDENT);
+        inputs.push(quote_arg!(cx, $context_ident: $context));
+    }
 
-    Some(item)
+    inputs
 }
 
 fn strip_node_ids(item: P<ast::Item>) -> P<ast::Item> {
@@ -168,9 +342,374 @@ pub struct Builder<'a, 'b: 'a> {
     cx: &'a ExtCtxt<'b>,
     ast_builder: AstBuilder,
     mir: &'a Mir,
+    resume_blocks: HashSet<BasicBlock>,
+    /// Suspend with `Some(())` instead of the yielded value, for the `specialize` option.
+    skip_items: bool,
 }
 
 impl<'a, 'b: 'a> Builder<'a, 'b> {
+    /// Create the closure that takes a single step from the state it's passed.
+    fn step_closure(&self, state_arms: Vec<ast::Arm>) -> P<ast::Expr> {
+        let cx = self.cx;
+        let mir = self.mir;
+
+        // States only `continue` the loop when they jump to another state within the same step.
+        // If every state ends by suspending or returning, like in a generator that just yields a
+        // few values in a row, the loop is dead and we can leave it out.
+        let jumps_between_states = mir.basic_blocks().iter()
+            .any(|block_data| {
+                match block_data.terminator().kind {
+                    TerminatorKind::Goto { .. } |
+                    TerminatorKind::If { .. } |
+                    TerminatorKind::Match { .. } => true,
+                    TerminatorKind::Return |
+                    TerminatorKind::Unreachable |
+                    TerminatorKind::Suspend { .. } => false,
+                }
+            });
+
+        // The state is swapped out for the illegal state while stepping, so that's where a
+        // generator is left once it has finished, or if a step panicked. Keep returning `None`
+        // from then on, rather than panicking again.
+        let illegal_state = match mir.state_machine_kind {
+            StateMachineKind::Generator => {
+                quote_expr!(cx, {
+                    return (::std::option::Option::None, State::Illegal);
+                })
+            }
+            StateMachineKind::Async => {
+                quote_expr!(cx, {
+                    unreachable!("illegal state")
+                })
+            }
+        };
+
+        let on_resume = self.on_resume_stmt();
+
+        // The step closure only captures the context, if there is one, which it takes ownership
+        // of so the state machine can outlive the function that created it.
+        let capture_by = if mir.options.context.is_some() {
+            quote_tokens!(cx, move)
+        } else {
+            vec![]
+        };
+
+        if jumps_between_states {
+            quote_expr!(cx,
+                $capture_by |mut state| {
+                    $on_resume
+                    loop {
+                        match state {
+                            $state_arms
+                            State::Illegal => $illegal_state
+                        }
+                    }
+                }
+            )
+        } else {
+            quote_expr!(cx,
+                $capture_by |state| {
+                    $on_resume
+                    match state {
+                        $state_arms
+                        State::Illegal => $illegal_state
+                    }
+                }
+            )
+        }
+    }
+
+    /// Create the constructor of a generator's state machine. When specialized, the state machine
+    /// steps through a pair of closures, the second of which doesn't build the yielded values.
+    fn generator_new_impl(&self, specialize: bool) -> P<ast::Item> {
+        let cx = self.cx;
+
+        let (skip_ty_param, next_ty, skip_bound) = if specialize {
+            (
+                quote_tokens!(cx, G,),
+                quote_tokens!(cx, (F, G)),
+                quote_tokens!(cx, G: Fn(S) -> (::std::option::Option<()>, S),),
+            )
+        } else {
+            (vec![], quote_tokens!(cx, F), vec![])
+        };
+
+        self.expect_generated(quote_item!(cx,
+            impl<S, F, $skip_ty_param Item> StateMachine<S, $next_ty>
+                where S: ::std::default::Default,
+                      F: Fn(S) -> (::std::option::Option<Item>, S),
+                      $skip_bound
+            {
+                fn new(initial_state: S, next: $next_ty) -> Self {
+                    StateMachine {
+                        state: initial_state,
+                        next: next,
+                    }
+                }
+            }
+        ), "`StateMachine` impl")
+    }
+
+    /// Create the `Iterator` impl of a generator's state machine. When specialized, `count` uses
+    /// the closure that doesn't build the yielded values, and both `count` and `last` step through
+    /// the state machine directly, since they consume it.
+    fn iterator_impl(&self, specialize: bool) -> P<ast::Item> {
+        let cx = self.cx;
+
+        let (skip_ty_param, next_ty, skip_bound, step_field, specialized_methods) = if specialize {
+            let specialized_methods = quote_tokens!(cx,
+                fn count(self) -> usize {
+                    let StateMachine { mut state, next: (_, skip) } = self;
+                    let mut count = 0;
+                    loop {
+                        let (value, next_state) = skip(state);
+                        match value {
+                            ::std::option::Option::Some(()) => { count += 1; }
+                            ::std::option::Option::None => { return count; }
+                        }
+                        state = next_state;
+                    }
+                }
+
+                fn last(self) -> ::std::option::Option<Item> {
+                    let StateMachine { mut state, next: (next, _) } = self;
+                    let mut last = ::std::option::Option::None;
+                    loop {
+                        let (value, next_state) = next(state);
+                        match value {
+                            ::std::option::Option::Some(value) => {
+                                last = ::std::option::Option::Some(value);
+                            }
+                            ::std::option::Option::None => { return last; }
+                        }
+                        state = next_state;
+                    }
+                }
+            );
+
+            (
+                quote_tokens!(cx, G,),
+                quote_tokens!(cx, (F, G)),
+                quote_tokens!(cx, G: Fn(S) -> (::std::option::Option<()>, S),),
+                quote_tokens!(cx, .0),
+                specialized_methods,
+            )
+        } else {
+            (vec![], quote_tokens!(cx, F), vec![], vec![], vec![])
+        };
+
+        self.expect_generated(quote_item!(cx,
+            impl<S, F, $skip_ty_param Item> ::std::iter::Iterator for StateMachine<S, $next_ty>
+                where S: ::std::default::Default,
+                      F: Fn(S) -> (::std::option::Option<Item>, S),
+                      $skip_bound
+            {
+                type Item = Item;
+
+                fn next(&mut self) -> ::std::option::Option<Item> {
+                    let state = ::std::mem::replace(&mut self.state, S::default());
+                    let (value, state) = (self.next $step_field)(state);
+                    self.state = state;
+                    value
+                }
+
+                $specialized_methods
+            }
+        ), "`Iterator` impl")
+    }
+
+    /// Create the `{name}_drain_into` function, which runs the generator into an `Extend` sink.
+    /// It takes the steps itself, so unlike `Iterator::next` it doesn't swap a default state in
+    /// and out for every item.
+    fn drain_fn(&self,
+                state_machine_struct: &P<ast::Item>,
+                state_enum: &P<ast::Item>,
+                state_default: &P<ast::Item>,
+                self_binding: &Option<ast::Stmt>,
+                state_machine: &P<ast::Expr>) -> Option<P<ast::Item>> {
+        let cx = self.cx;
+        let mir = self.mir;
+
+        let item_ty = match mir.fn_decl.item_ty() {
+            Some(item_ty) => item_ty,
+            None => {
+                cx.span_err(
+                    mir.fn_decl.return_ty().span,
+                    "`drain_into` requires the return type to specify the `Item` type");
+                return None;
+            }
+        };
+
+        let items = &mir.items;
+
+        let block = quote_block!(cx, {
+            $items
+            $state_machine_struct
+
+            impl<S, F, Item> StateMachine<S, F>
+                where F: Fn(S) -> (::std::option::Option<Item>, S),
+            {
+                fn new(initial_state: S, next: F) -> Self {
+                    StateMachine {
+                        state: initial_state,
+                        next: next,
+                    }
+                }
+
+                fn drain_into<C>(self, sink: &mut C)
+                    where C: ::std::iter::Extend<Item>
+                {
+                    let StateMachine { mut state, next } = self;
+                    loop {
+                        let (value, next_state) = next(state);
+                        match value {
+                            ::std::option::Option::Some(value) => {
+                                sink.extend(::std::option::Option::Some(value));
+                            }
+                            ::std::option::Option::None => { return; }
+                        }
+                        state = next_state;
+                    }
+                }
+            }
+
+            $state_enum
+            $state_default
+            $self_binding
+            $state_machine.drain_into(__stateful_sink)
+        });
+
+        let sink_ty_param = self.ast_builder.ty_param("__StatefulSink")
+            .trait_bound(quote_path!(cx, ::std::iter::Extend<$item_ty>)).build()
+            .build();
+
+        let item = self.ast_builder.item()
+            .fn_(self.ast_builder.id(format!("{}_drain_into", mir.fn_decl.ident())))
+            .with_args(signature_inputs(cx, mir))
+            .with_arg(quote_arg!(cx, __stateful_sink: &mut __StatefulSink))
+            .default_return()
+            .generics()
+                .with(mir.fn_decl.generics().clone())
+                .with_ty_param(sink_ty_param)
+                .build()
+            .build(block);
+
+        Some(item)
+    }
+
+    /// Create the `{name}_steps` function, which iterates over each step of the state machine,
+    /// pairing the value produced by the step with the name of the state it transitioned into.
+    /// This lets the transitions be tested directly.
+    fn steps_fn(&self,
+                state_machine_struct: &P<ast::Item>,
+                state_machine_impl: &P<ast::Item>,
+                state_enum: &P<ast::Item>,
+                state_default: &P<ast::Item>,
+                self_binding: &Option<ast::Stmt>,
+                state_machine: &P<ast::Expr>) -> Option<P<ast::Item>> {
+        let cx = self.cx;
+        let mir = self.mir;
+        let return_ty = mir.fn_decl.return_ty();
+
+        if let StateMachineKind::Async = mir.state_machine_kind {
+            cx.span_err(mir.span, "`expose_steps` is only supported by generators");
+            return None;
+        }
+
+        let item_ty = match mir.fn_decl.item_ty() {
+            Some(item_ty) => item_ty,
+            None => {
+                cx.span_err(
+                    return_ty.span,
+                    "`expose_steps` requires the return type to specify the `Item` type");
+                return None;
+            }
+        };
+
+        let state_name_trait = self.state_name_trait();
+        let state_name_impl = self.state_name_impl();
+
+        let (steps_ty, steps_expr) = match return_ty.node {
+            ast::TyKind::ImplTrait(_) => {
+                let ty = quote_ty!(cx,
+                    impl ::std::iter::Iterator<
+                        Item=(::std::option::Option<$item_ty>, &'static str)>);
+
+                (ty, state_machine.clone())
+            }
+            _ => {
+                let ty = quote_ty!(cx,
+                    Box<::std::iter::Iterator<
+                        Item=(::std::option::Option<$item_ty>, &'static str)>>);
+
+                (ty, self.ast_builder.expr().box_().build(state_machine.clone()))
+            }
+        };
+
+        // The steps are only ever taken over this function's state enum, so the `Iterator` impl
+        // can name it, and tell when the state machine has finished by matching on its state.
+        let (state_generics, state_path) = self.state_generics_and_path();
+        let impl_generics = self.ast_builder.generics()
+            .with(state_generics)
+            .ty_param_id("F")
+            .ty_param_id("Item")
+            .build();
+
+        let items = &mir.items;
+
+        let block = quote_block!(cx, {
+            $items
+            $state_machine_struct
+            $state_name_trait
+            $state_machine_impl
+
+            impl $impl_generics ::std::iter::Iterator for StateMachine<$state_path, F>
+                where F: Fn($state_path) -> (::std::option::Option<Item>, $state_path)
+            {
+                type Item = (::std::option::Option<Item>, &'static str);
+
+                fn next(&mut self) -> ::std::option::Option<Self::Item> {
+                    // The state machine is left in the illegal state once it's finished.
+                    if let State::Illegal = self.state {
+                        return ::std::option::Option::None;
+                    }
+
+                    let state = ::std::mem::replace(&mut self.state, State::Illegal);
+                    let (value, state) = (self.next)(state);
+                    let name = state.state_name();
+                    self.state = state;
+                    ::std::option::Option::Some((value, name))
+                }
+            }
+
+            $state_enum
+            $state_default
+            $state_name_impl
+            $self_binding
+            $steps_expr
+        });
+
+        let item = self.ast_builder.item()
+            .fn_(self.ast_builder.id(format!("{}_steps", mir.fn_decl.ident())))
+            .with_args(signature_inputs(cx, mir))
+            .build_return(steps_ty)
+            .generics().with(mir.fn_decl.generics().clone()).build()
+            .build(block);
+
+        Some(item)
+    }
+
+    /// Unwrap the result of a `quote_item!` or `quote_stmt!`, which fails if the generated tokens
+    /// don't parse. That's always a bug in the translation, so report which construct it was.
+    pub fn expect_generated<T>(&self, generated: Option<T>, what: &str) -> T {
+        match generated {
+            Some(generated) => generated,
+            None => {
+                span_bug!(self.cx, self.mir.span, "failed to generate the {}", what);
+            }
+        }
+    }
+
     pub fn shadowed_ident(&self, local: Local) -> ast::Ident {
         let decl_ident = self.mir.local_decls[local].name;
         self.ast_builder.id(format!("{}_shadowed_{}", decl_ident, local.index()))
@@ -3,6 +3,7 @@ use mir::*;
 use std::collections::HashSet;
 use syntax::ast;
 use syntax::codemap::Span;
+use syntax::print::pprust;
 use syntax::ptr::P;
 use translate::Builder;
 
@@ -36,10 +37,13 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             let mut decls = vec![];
 
             for live_decl in live_decls {
-                // Only add active decls to the state.
+                // Only add active decls to the state. Recomputed decls are bound when entering
+                // the state instead.
                 let local = match *live_decl {
                     LiveDecl::Active(local) => {
-                        decls.push((local, self.mir.local_decls[local].name));
+                        if self.mir.local_decls[local].recompute.is_none() {
+                            decls.push((local, self.mir.local_decls[local].name));
+                        }
                         local
                     }
                     LiveDecl::Moved(local) => local,
@@ -90,6 +94,175 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
         }
     }
 
+    /// Emit a note for each state listing the locals it captures, then one naming the state that
+    /// captures the most. Most local types are inferred, so we can't report sizes in bytes, but
+    /// this points at the state that likely dominates the size of the state machine.
+    pub fn report_state_sizes(&self) {
+        let diagnostic = &self.cx.parse_sess.span_diagnostic;
+        let mut largest: Option<(BasicBlock, Vec<String>)> = None;
+
+        for block in self.mir.basic_blocks().indices() {
+            let captures = self.get_incoming_scope_decls(block).into_iter()
+                .flat_map(|(_, decls)| decls)
+                .map(|(local, ident)| {
+                    match self.mir.local_decls[local].ty {
+                        Some(ref ty) => format!("`{}: {}`", ident, pprust::ty_to_string(ty)),
+                        None => format!("`{}: _`", ident),
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            if captures.is_empty() {
+                continue;
+            }
+
+            diagnostic.span_note_without_error(
+                self.block_span(block),
+                &format!("state `{}` captures {}",
+                         self.state_id(block),
+                         captures.join(", ")));
+
+            let is_largest = match largest {
+                Some((_, ref largest_captures)) => captures.len() > largest_captures.len(),
+                None => true,
+            };
+
+            if is_largest {
+                largest = Some((block, captures));
+            }
+        }
+
+        if let Some((block, captures)) = largest {
+            diagnostic.span_note_without_error(
+                self.mir.span,
+                &format!("the largest state is `{}`, which captures {}",
+                         self.state_id(block),
+                         captures.join(", ")));
+        }
+    }
+
+    /// Declare the `StateName` trait, which lets the state machine ask its state for its name.
+    pub fn state_name_trait(&self) -> P<ast::Item> {
+        self.expect_generated(quote_item!(self.cx,
+            trait StateName {
+                fn state_name(&self) -> &'static str;
+            }
+        ), "`StateName` trait")
+    }
+
+    /// Matches the state of a block without binding any of its scopes.
+    fn state_wildcard_pat(&self, block: BasicBlock) -> P<ast::Pat> {
+        let state_path = self.state_path(block);
+
+        if self.get_incoming_scope_decls(block).is_empty() {
+            quote_pat!(self.cx, $state_path)
+        } else {
+            quote_pat!(self.cx, $state_path(..))
+        }
+    }
+
+    /// Calls the `on_resume` hook when the step function is entered in a state we suspended to.
+    /// This is checked once on entry rather than in the state arms, since SimplifyCfg can make
+    /// the block we resume into also be the target of a jump from another state.
+    pub fn on_resume_stmt(&self) -> Option<ast::Stmt> {
+        let on_resume = match self.mir.options.on_resume {
+            Some(ref on_resume) => on_resume,
+            None => { return None; }
+        };
+
+        let pats = self.mir.basic_blocks().indices()
+            .filter(|block| self.resume_blocks.contains(block))
+            .map(|block| self.state_wildcard_pat(block))
+            .collect::<Vec<_>>();
+
+        if pats.is_empty() {
+            return None;
+        }
+
+        let ast_builder = self.ast_builder.span(self.mir.span);
+
+        let match_expr = ast_builder.expr().match_()
+            .build(quote_expr!(self.cx, state))
+            .with_arm(
+                ast_builder.arm()
+                    .with_pats(pats)
+                    .body().build(quote_expr!(self.cx, $on_resume()))
+            )
+            .with_arm(ast_builder.arm().pat().wild().body().unit())
+            .build();
+
+        Some(ast_builder.stmt().semi().build(match_expr))
+    }
+
+    /// Create an impl of a `StateName` trait for the state enum, which returns the name of the
+    /// current state. The trait is declared by `state_name_trait`.
+    pub fn state_name_impl(&self) -> P<ast::Item> {
+        let ast_builder = self.ast_builder.span(self.mir.span);
+
+        let mut arms = self.mir.basic_blocks().indices()
+            .map(|block| {
+                let pat = self.state_wildcard_pat(block);
+                let name = self.state_id(block).name.as_str();
+
+                ast_builder.arm()
+                    .with_pat(pat)
+                    .body().lit().str(&*name)
+            })
+            .collect::<Vec<_>>();
+
+        arms.push(
+            ast_builder.arm()
+                .with_pat(quote_pat!(self.cx, State::Illegal))
+                .body().lit().str("Illegal")
+        );
+
+        let (generics, state_path) = self.state_generics_and_path();
+
+        let match_expr = ast_builder.expr().match_()
+            .build(quote_expr!(self.cx, *self))
+            .with_arms(arms)
+            .build();
+
+        self.expect_generated(quote_item!(self.cx,
+            impl $generics StateName for $state_path {
+                fn state_name(&self) -> &'static str {
+                    $match_expr
+                }
+            }
+        ), "`StateName` impl")
+    }
+
+    /// The generics of the state enum, which has a type parameter for every decl held in one of
+    /// its variants, and the path to the enum with those parameters.
+    pub fn state_generics_and_path(&self) -> (ast::Generics, ast::Path) {
+        let mut ty_param_ids = Vec::new();
+        let mut seen_ty_param_ids = HashSet::new();
+
+        for block in self.mir.basic_blocks().indices() {
+            let (_, tp) = self.state_variant(block);
+
+            for ty_param_id in tp {
+                if seen_ty_param_ids.insert(ty_param_id) {
+                    ty_param_ids.push(ty_param_id);
+                }
+            }
+        }
+
+        let generics = self.ast_builder.generics()
+            .with_ty_param_ids(ty_param_ids.iter())
+            .build();
+
+        let state_path = self.ast_builder.path()
+            .segment("State")
+                .with_tys(
+                    ty_param_ids.iter()
+                        .map(|variable| self.ast_builder.ty().id(variable))
+                )
+            .build()
+            .build();
+
+        (generics, state_path)
+    }
 
     pub fn state_enum_default_and_arms(&self) -> (P<ast::Item>, P<ast::Item>, Vec<ast::Arm>) {
         let all_basic_blocks = self.mir.basic_blocks();
@@ -114,6 +287,8 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
 
             state_variants.push(variant);
 
+            lowering_trace!("state_arm: block={:?} state={}", block, self.state_id(block));
+
             let arm = self.state_arm(block);
             state_arms.push(arm);
         }
@@ -137,17 +312,20 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             .build()
             .build();
 
-        let state_default = quote_item!(self.cx,
+        let state_default = self.expect_generated(quote_item!(self.cx,
             impl $generics ::std::default::Default for $state_path {
                 fn default() -> Self {
                     State::Illegal
                 }
             }
-        ).expect("state default item");
+        ), "`Default` impl for the state");
 
         (state_enum, state_default, state_arms)
     }
 
+    /// Create the variant for a block. A variant only holds the decls that are live when entering
+    /// its block, rather than every local in the function, so a large local that's never held
+    /// across a block boundary doesn't take up any space in the state machine.
     fn state_variant(&self, block: BasicBlock) -> (ast::Variant, Vec<ast::Ident>) {
         let span = self.block_span(block);
         let ast_builder = self.ast_builder.span(span);
@@ -208,9 +386,11 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                 .build()
         };
 
+        let stmts = self.block(block);
+
         // First, setup the blocks.
         let mut body = ast_builder.block()
-            .with_stmts(self.block(block))
+            .with_stmts(stmts)
             .build();
 
         for &(scope, ref decls) in scope_decls.iter().rev() {
@@ -232,6 +412,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
 
             body = ast_builder.block()
                 .stmt().build(stmt)
+                .with_stmts(self.recompute_stmts(block, scope))
                 .expr().build_block(body);
         }
 
@@ -239,4 +420,33 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             .with_pat(pat)
             .body().build_block(body)
     }
+
+    /// Bind the decls of `scope` that are declared with `recompute!` and live when entering
+    /// `block`, by evaluating their initializers again.
+    fn recompute_stmts(&self, block: BasicBlock, scope: VisibilityScope) -> Vec<ast::Stmt> {
+        let live_decls = match self.mir[block].incoming_decls.get(&scope) {
+            Some(live_decls) => live_decls,
+            None => { return vec![]; }
+        };
+
+        live_decls.iter()
+            .filter_map(|live_decl| {
+                let local = match *live_decl {
+                    LiveDecl::Active(local) => local,
+                    LiveDecl::Moved(_) => { return None; }
+                };
+
+                let local_decl = &self.mir.local_decls[local];
+
+                local_decl.recompute.as_ref().map(|expr| {
+                    let ast_builder = self.ast_builder.span(expr.span);
+
+                    ast_builder.stmt().let_()
+                        .build(ast_builder.pat().id(local_decl.name))
+                        .build_option_ty(local_decl.ty.clone())
+                        .expr().build(expr.clone())
+                })
+            })
+            .collect()
+    }
 }
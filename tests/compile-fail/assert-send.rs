@@ -0,0 +1,12 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+use std::rc::Rc;
+
+#[generator(assert_send)]
+fn gen(value: Rc<usize>) -> Box<Iterator<Item=usize>> { //~ ERROR std::marker::Send
+    yield_!(*value);
+    yield_!(*value);
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator]
+fn gen(values: Vec<usize>) -> Box<Iterator<Item=usize>> {
+    for value in values {
+        yield_!(copied!(value));
+        break value; //~ ERROR cannot break with a value out of a `for` loop
+    }
+}
+
+fn main() {}
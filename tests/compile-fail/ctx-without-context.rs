@@ -0,0 +1,10 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator]
+fn gen() -> Box<Iterator<Item=usize>> {
+    let step = ctx!().step; //~ ERROR `ctx!()` requires a context
+    yield_!(step);
+}
+
+fn main() {}
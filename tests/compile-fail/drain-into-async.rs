@@ -0,0 +1,13 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+extern crate futures;
+
+use futures::Future;
+
+#[async(drain_into)]
+fn read() -> Box<Future<Item=usize, Error=()>> { //~ ERROR `drain_into` is only supported by generators
+    1
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator]
+fn gen() { //~ ERROR `generator` functions must specify the `Item` type in their return type
+    yield_!(1);
+}
+
+fn main() {}
@@ -0,0 +1,20 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator]
+fn assigned(mut n: usize) -> Box<Iterator<Item=usize>> {
+    let squared = recompute!(n * n);
+    n += 1; //~ ERROR `n` can't be assigned while `squared` is recomputed from it
+    yield_!(copied!(n));
+    yield_!(squared);
+}
+
+#[generator]
+fn moved(values: Vec<usize>) -> Box<Iterator<Item=usize>> {
+    let len = recompute!(values.len());
+    let taken = values; //~ ERROR `values` can't be moved while `len` is recomputed from it
+    yield_!(taken[0]);
+    yield_!(len);
+}
+
+fn main() {}
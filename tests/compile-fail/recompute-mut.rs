@@ -0,0 +1,12 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator]
+fn gen(n: usize) -> Box<Iterator<Item=usize>> {
+    let mut squared = recompute!(n * n); //~ ERROR `recompute!` can only initialize an immutable binding
+    yield_!(copied!(squared));
+    squared += 1;
+    yield_!(squared);
+}
+
+fn main() {}
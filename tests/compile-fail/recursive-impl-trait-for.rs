@@ -0,0 +1,14 @@
+#![feature(plugin, conservative_impl_trait)]
+#![plugin(stateful)]
+
+#[generator]
+fn countdown(n: usize) -> impl Iterator<Item=usize> {
+    if n > 0 {
+        yield_!(n);
+        for value in countdown(n - 1) { //~ ERROR recursive call to `countdown` would make the state machine infinitely sized
+            yield_!(value);
+        }
+    }
+}
+
+fn main() {}
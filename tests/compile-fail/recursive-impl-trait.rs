@@ -0,0 +1,12 @@
+#![feature(plugin, conservative_impl_trait)]
+#![plugin(stateful)]
+
+#[generator]
+fn countdown(n: usize) -> impl Iterator<Item=usize> {
+    if n > 0 {
+        yield_!(n);
+        yield_from!(countdown(n - 1)); //~ ERROR recursive call to `countdown` would make the state machine infinitely sized
+    }
+}
+
+fn main() {}
@@ -0,0 +1,13 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator]
+fn windows<'a>(buf: Vec<u8>) -> Box<Iterator<Item=&'a [u8]> + 'a> {
+    yield_!(&buf[0..2]); //~ ERROR cannot yield a reference to `buf`, which is owned by the generator
+}
+
+fn main() {
+    for window in windows(vec![1, 2, 3]) {
+        println!("{:?}", window);
+    }
+}
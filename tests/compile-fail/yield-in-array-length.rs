@@ -0,0 +1,10 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator]
+fn gen() -> Box<Iterator<Item=usize>> {
+    let values = [0; { yield_!(1); 2 }]; //~ ERROR `yield_!` cannot be used in an array length
+    yield_!(values.len());
+}
+
+fn main() {}
@@ -0,0 +1,15 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator]
+fn gen(values: Vec<usize>) -> Box<Iterator<Item=usize>> {
+    let doubled = values.into_iter()
+        .map(|value| { yield_!(value); value * 2 }) //~ ERROR `yield_!` cannot be used inside a closure
+        .collect::<Vec<_>>();
+
+    for value in doubled {
+        yield_!(value);
+    }
+}
+
+fn main() {}
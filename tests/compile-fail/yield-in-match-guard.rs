@@ -0,0 +1,14 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator]
+fn gen(value: Option<usize>) -> Box<Iterator<Item=usize>> {
+    match value {
+        Some(x) if { yield_!(x); x > 1 } => { //~ ERROR `yield_!` cannot be used in a match guard
+            yield_!(x);
+        }
+        _ => {}
+    }
+}
+
+fn main() {}
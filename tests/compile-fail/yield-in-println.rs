@@ -0,0 +1,9 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator]
+fn gen() -> Box<Iterator<Item=usize>> {
+    println!("{:?}", yield_!(1)); //~ ERROR `yield_!` cannot be used inside `println!`
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator(item = "usize")]
+fn gen() -> Box<Iterator<Item=usize>> {
+    yield_!(1);
+    yield_!("two"); //~ ERROR mismatched types
+}
+
+fn main() {}
@@ -0,0 +1,14 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+extern crate futures;
+
+use futures::Future;
+
+#[async]
+fn future() -> Box<Future<Item=usize, Error=()>> {
+    let value = await!(futures::finished::<usize, ()>(1));
+    value + 1
+}
+
+fn main() {}
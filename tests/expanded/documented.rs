@@ -0,0 +1,12 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+/// Counts up to `n`.
+#[generator]
+fn count(n: usize) -> Box<Iterator<Item=usize>> {
+    for i in 0..n {
+        yield_!(i);
+    }
+}
+
+fn main() {}
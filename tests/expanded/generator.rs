@@ -0,0 +1,11 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator]
+fn gen(n: usize) -> Box<Iterator<Item=usize>> {
+    for i in 0..n {
+        yield_!(i);
+    }
+}
+
+fn main() {}
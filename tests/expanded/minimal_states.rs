@@ -0,0 +1,21 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator]
+fn gen(values: Vec<Option<usize>>, limit: usize) -> Box<Iterator<Item=usize>> {
+    match copied!(values).len() {
+        0 => { return; }
+        _ => {
+            for value in values {
+                if let Some(value) = value {
+                    if copied!(value) > copied!(limit) {
+                        return;
+                    }
+                    yield_!(value);
+                }
+            }
+        }
+    }
+}
+
+fn main() {}
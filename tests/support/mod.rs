@@ -0,0 +1,33 @@
+//! Shared by the tests that run rustc with the plugin on a source file and check its output.
+
+use std::env;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Run rustc with the plugin on `path`, relative to the crate root, passing along `args`.
+pub fn rustc(path: &str, args: &[&str]) -> Output {
+    let mut command = Command::new("rustc");
+
+    for dir in library_dirs() {
+        command.arg("-L").arg(dir);
+    }
+
+    command.args(args)
+        .arg(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(path))
+        .output()
+        .expect("failed to run rustc")
+}
+
+/// The plugin and its dependencies are built next to the running test, so look for them there
+/// rather than assuming a target directory or profile.
+fn library_dirs() -> Vec<PathBuf> {
+    let exe = env::current_exe().expect("failed to find the test executable");
+    let mut dir = exe.parent().expect("test executable has no directory").to_path_buf();
+
+    if dir.ends_with("deps") {
+        dir.pop();
+    }
+
+    let deps_dir = dir.join("deps");
+    vec![dir, deps_dir]
+}
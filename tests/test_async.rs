@@ -0,0 +1,51 @@
+#![feature(plugin, type_ascription)]
+#![plugin(stateful)]
+#![allow(dead_code)]
+#![allow(non_shorthand_field_patterns)]
+#![allow(unused_must_use)]
+#![allow(unused_mut)]
+#![allow(unused_variables)]
+
+extern crate futures;
+
+use futures::Future;
+
+#[test]
+fn test_tail_expr() {
+    #[async]
+    fn future() -> Box<Future<Item=usize, Error=()>> {
+        await!(futures::finished::<(), ()>(()));
+        42
+    }
+
+    assert_eq!(future().wait(), Ok(42));
+}
+
+#[test]
+fn test_tail_match() {
+    #[async]
+    fn future(value: Option<usize>) -> Box<Future<Item=usize, Error=()>> {
+        match value {
+            Some(value) => {
+                let offset = await!(futures::finished::<usize, ()>(100));
+                value + offset
+            }
+            None => 0,
+        }
+    }
+
+    assert_eq!(future(Some(1)).wait(), Ok(101));
+    assert_eq!(future(None).wait(), Ok(0));
+}
+
+#[test]
+fn test_type_ascription() {
+    #[async]
+    fn future() -> Box<Future<Item=u64, Error=()>> {
+        // The ascription is what picks the integer type of the awaited value.
+        let value = await!(futures::finished::<_, ()>(1)) : u32;
+        value as u64 + 1
+    }
+
+    assert_eq!(future().wait(), Ok(2));
+}
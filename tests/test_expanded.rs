@@ -0,0 +1,58 @@
+//! Snapshot-style checks of the code we generate, by having rustc print the expanded source
+//! instead of compiling it.
+
+mod support;
+
+fn expand(path: &str) -> String {
+    let output = support::rustc(path, &["-Z", "unpretty=expanded"]);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_expanded_generator() {
+    let expanded = expand("tests/expanded/generator.rs");
+
+    assert!(expanded.contains("fn gen(n: usize) -> Box<Iterator<Item = usize>>"));
+    assert!(expanded.contains("struct StateMachine<S, F>"));
+    assert!(expanded.contains("enum State"));
+    assert!(expanded.contains("State::Illegal"));
+    assert!(expanded.contains("::std::iter::Iterator for StateMachine<S, F>"));
+    assert!(!expanded.contains("yield_!"));
+    assert!(expanded.contains("Generated by `#[generator]` as a state machine with"));
+}
+
+/// The blocks left after the early returns and the `break` out of the `for` loop are dead and
+/// removed. Each scope a `return` or `break` exits gets its own drop block, and keeping all of
+/// those used to give this generator 38 states. SimplifyCfg merges each drop block into the block
+/// that exits the scope, so the count has to stay under that.
+#[test]
+fn test_expanded_minimal_states() {
+    let expanded = expand("tests/expanded/minimal_states.rs");
+
+    let prefix = "Generated by `#[generator]` as a state machine with ";
+    let start = expanded.find(prefix).expect("missing state count") + prefix.len();
+    let count = expanded[start..].split(' ').next().unwrap().parse::<usize>().unwrap();
+
+    assert!(count < 38, "{} states", count);
+}
+
+#[test]
+fn test_expanded_async() {
+    let expanded = expand("tests/expanded/async.rs");
+
+    assert!(expanded.contains("enum State"));
+    assert!(expanded.contains("::futures::Future for StateMachine<S, F>"));
+    assert!(expanded.contains("::futures::Async::Ready"));
+    assert!(!expanded.contains("await!"));
+}
+
+#[test]
+fn test_expanded_keeps_original_docs() {
+    let expanded = expand("tests/expanded/documented.rs");
+
+    assert!(expanded.contains("Counts up to `n`."));
+    assert!(!expanded.contains("Generated by `#[generator]`"));
+}
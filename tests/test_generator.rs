@@ -1,5 +1,5 @@
-#![feature(plugin)]
-#![cfg_attr(feature = "impl_trait", feature(conservative_impl_trait))]
+#![feature(plugin, inclusive_range_syntax, asm)]
+#![cfg_attr(feature = "impl_trait", feature(conservative_impl_trait, closure_to_fn_coercion))]
 #![plugin(stateful)]
 #![allow(dead_code)]
 #![allow(non_shorthand_field_patterns)]
@@ -277,6 +277,1906 @@ fn test_yield_in_assign() {
     assert_eq!(gen.next(), None);
 }
 
+#[test]
+fn test_while_let_continue() {
+    #[generator]
+    fn gen<T: 'static>(items: Vec<Option<T>>) -> Box<Iterator<Item=T>> {
+        let mut iter = items.into_iter();
+        while let Some(item) = copied!(iter).next() {
+            let item = match item {
+                Some(item) => item,
+                None => { continue; }
+            };
+
+            yield_!(item);
+        }
+    }
+
+    let mut gen = gen(vec![Some(1), None, Some(2), None, None, Some(3)]);
+    assert_eq!(gen.next(), Some(1));
+    assert_eq!(gen.next(), Some(2));
+    assert_eq!(gen.next(), Some(3));
+    assert_eq!(gen.next(), None);
+}
+
+#[test]
+fn test_for_labeled_break() {
+    #[generator]
+    fn gen() -> Box<Iterator<Item=usize>> {
+        'outer: for i in 0..4 {
+            for j in 0..4 {
+                if copied!(j) > copied!(i) {
+                    continue 'outer;
+                }
+
+                if copied!(i) == 3 {
+                    break 'outer;
+                }
+
+                yield_!(copied!(i) * 10 + j);
+            }
+        }
+    }
+
+    let items: Vec<_> = gen().collect();
+    assert_eq!(items, vec![0, 10, 11, 20, 21, 22]);
+}
+
+#[test]
+fn test_specialize_count_last() {
+    #[generator(specialize)]
+    fn gen(n: usize) -> Box<Iterator<Item=usize>> {
+        let mut i = 0;
+        while copied!(i) < copied!(n) {
+            yield_!(copied!(i) * 2);
+            i += 1;
+        }
+    }
+
+    let mut count = 0;
+    for _ in gen(5) {
+        count += 1;
+    }
+
+    assert_eq!(gen(5).count(), count);
+    assert_eq!(gen(0).count(), 0);
+    assert_eq!(gen(5).last(), Some(8));
+    assert_eq!(gen(0).last(), None);
+}
+
+#[test]
+fn test_match_yield_discriminant() {
+    struct Point {
+        x: usize,
+        y: usize,
+    }
+
+    #[generator]
+    fn gen(points: Vec<Point>) -> Box<Iterator<Item=usize>> {
+        let mut iter = points.into_iter();
+        match { yield_!(0); copied!(iter).next() } {
+            Some(Point { x, .. }) => {
+                yield_!(x);
+            }
+            None => {
+                yield_!(100);
+            }
+        }
+    }
+
+    let mut iter = gen(vec![Point { x: 1, y: 2 }]);
+    assert_eq!(iter.next(), Some(0));
+    assert_eq!(iter.next(), Some(1));
+    assert_eq!(iter.next(), None);
+
+    let mut iter = gen(vec![]);
+    assert_eq!(iter.next(), Some(0));
+    assert_eq!(iter.next(), Some(100));
+    assert_eq!(iter.next(), None);
+}
+
+#[test]
+fn test_drain_into() {
+    #[generator(drain_into)]
+    fn gen(n: usize) -> Box<Iterator<Item=usize>> {
+        let mut i = 0;
+        while copied!(i) < copied!(n) {
+            yield_!(copied!(i));
+            i += 1;
+        }
+    }
+
+    let mut drained = Vec::new();
+    gen_drain_into(5, &mut drained);
+    assert_eq!(drained, gen(5).collect::<Vec<_>>());
+
+    // Draining appends to what's already in the sink.
+    gen_drain_into(2, &mut drained);
+    assert_eq!(drained, vec![0, 1, 2, 3, 4, 0, 1]);
+}
+
+#[test]
+fn test_drain_into_skips_iterator() {
+    // Records the `size_hint` of every iterator it's extended with.
+    #[derive(Default)]
+    struct Sink {
+        hints: Vec<(usize, Option<usize>)>,
+        items: Vec<usize>,
+    }
+
+    impl Extend<usize> for Sink {
+        fn extend<I: IntoIterator<Item=usize>>(&mut self, iter: I) {
+            let iter = iter.into_iter();
+            self.hints.push(iter.size_hint());
+            self.items.extend(iter);
+        }
+    }
+
+    #[generator(drain_into)]
+    fn gen(n: usize) -> Box<Iterator<Item=usize>> {
+        let mut i = 0;
+        while copied!(i) < copied!(n) {
+            yield_!(copied!(i));
+            i += 1;
+        }
+    }
+
+    // Each item is handed over on its own, rather than through the boxed state machine, which
+    // doesn't know how many items are left.
+    let mut sink = Sink::default();
+    gen_drain_into(3, &mut sink);
+    assert_eq!(sink.items, vec![0, 1, 2]);
+    assert_eq!(sink.hints, vec![(1, Some(1)); 3]);
+}
+
+#[test]
+fn test_binary_op_yield() {
+    #[generator]
+    fn gen(deltas: Vec<usize>) -> Box<Iterator<Item=usize>> {
+        let mut prev = 0;
+        for delta in deltas {
+            let total = copied!(prev) + { yield_!(copied!(delta)); prev = 100; delta };
+            yield_!(copied!(total));
+            prev = total;
+        }
+    }
+
+    let mut gen = gen(vec![1, 2, 3]);
+    assert_eq!(gen.next(), Some(1));
+    assert_eq!(gen.next(), Some(1));
+    assert_eq!(gen.next(), Some(2));
+    assert_eq!(gen.next(), Some(3));
+    assert_eq!(gen.next(), Some(3));
+    assert_eq!(gen.next(), Some(6));
+    assert_eq!(gen.next(), None);
+}
+
+#[test]
+fn test_unary_op_yield() {
+    #[generator]
+    fn gen(values: Vec<isize>) -> Box<Iterator<Item=isize>> {
+        for value in values {
+            let negated = -{ yield_!(copied!(value)); value };
+            yield_!(negated);
+        }
+    }
+
+    let mut gen = gen(vec![1, -2]);
+    assert_eq!(gen.next(), Some(1));
+    assert_eq!(gen.next(), Some(-1));
+    assert_eq!(gen.next(), Some(-2));
+    assert_eq!(gen.next(), Some(2));
+    assert_eq!(gen.next(), None);
+}
+
+#[test]
+fn test_tail_expr() {
+    #[generator]
+    fn gen() -> Box<Iterator<Item=usize>> {
+        yield_!(1);
+        42
+    }
+
+    let mut gen = gen();
+    assert_eq!(gen.next(), Some(1));
+    assert_eq!(gen.next(), None);
+}
+
+#[test]
+fn test_short_circuit_yield() {
+    #[generator]
+    fn gen(a: bool, b: bool) -> Box<Iterator<Item=usize>> {
+        let and = copied!(a) && { yield_!(1); copied!(b) };
+        let or = copied!(a) || { yield_!(2); copied!(b) };
+        yield_!(if and { 10 } else { 20 });
+        yield_!(if or { 30 } else { 40 });
+    }
+
+    assert_eq!(gen(true, true).collect::<Vec<_>>(), vec![1, 10, 30]);
+    assert_eq!(gen(true, false).collect::<Vec<_>>(), vec![1, 20, 30]);
+    assert_eq!(gen(false, true).collect::<Vec<_>>(), vec![2, 20, 30]);
+    assert_eq!(gen(false, false).collect::<Vec<_>>(), vec![2, 20, 40]);
+}
+
+#[test]
+fn test_on_yield_on_resume_hooks() {
+    use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+    static YIELDS: AtomicUsize = ATOMIC_USIZE_INIT;
+    static RESUMES: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    fn count_yield(_value: &usize) {
+        YIELDS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn count_resume() {
+        RESUMES.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[generator(on_yield = "count_yield", on_resume = "count_resume")]
+    fn gen() -> Box<Iterator<Item=usize>> {
+        yield_!(1);
+        yield_!(2);
+        yield_!(3);
+    }
+
+    let mut iter = gen();
+    assert_eq!(iter.next(), Some(1));
+    assert_eq!(YIELDS.load(Ordering::SeqCst), 1);
+    assert_eq!(RESUMES.load(Ordering::SeqCst), 0);
+
+    assert_eq!(iter.collect::<Vec<_>>(), vec![2, 3]);
+    assert_eq!(YIELDS.load(Ordering::SeqCst), 3);
+    assert_eq!(RESUMES.load(Ordering::SeqCst), 3);
+}
+
+#[cfg(feature = "impl_trait")]
+#[test]
+fn test_state_variants_only_carry_live_decls() {
+    use std::mem;
+
+    #[generator]
+    fn gen(cond: bool) -> impl Iterator<Item=usize> {
+        let mut len = 0;
+        if cond {
+            let big = [1u8; 4096];
+            len = big.len();
+        }
+        yield_!(copied!(len));
+        yield_!(len);
+    }
+
+    #[generator]
+    fn gen_held(cond: bool) -> impl Iterator<Item=usize> {
+        let big = [1u8; 4096];
+        yield_!(1);
+        yield_!(big.len());
+    }
+
+    let iter = gen(true);
+    assert!(mem::size_of_val(&iter) < 4096);
+    assert_eq!(iter.collect::<Vec<_>>(), vec![4096, 4096]);
+
+    let iter = gen_held(true);
+    assert!(mem::size_of_val(&iter) >= 4096);
+    assert_eq!(iter.collect::<Vec<_>>(), vec![1, 4096]);
+}
+
+#[test]
+fn test_labeled_break_value() {
+    #[generator]
+    fn gen(n: usize) -> Box<Iterator<Item=usize>> {
+        let mut i = 0;
+        let result = 'outer: loop {
+            loop {
+                yield_!(copied!(i));
+                if copied!(i) == copied!(n) {
+                    break 'outer copied!(i) * 10;
+                }
+                i += 1;
+            }
+        };
+        yield_!(result);
+    }
+
+    assert_eq!(gen(2).collect::<Vec<_>>(), vec![0, 1, 2, 20]);
+}
+
+#[test]
+fn test_associated_function_call() {
+    struct Helper;
+
+    impl Helper {
+        fn double(value: usize) -> usize {
+            value * 2
+        }
+
+        fn convert<T: From<u8>>(value: u8) -> T {
+            T::from(value)
+        }
+
+        #[generator]
+        fn gen() -> Box<Iterator<Item=usize>> {
+            let x = Self::double({ yield_!(1); 2 });
+            yield_!(x);
+            let y = Helper::convert::<usize>({ yield_!(3); 4 });
+            yield_!(y);
+        }
+    }
+
+    assert_eq!(Helper::gen().collect::<Vec<_>>(), vec![1, 4, 3, 4]);
+}
+
+#[test]
+fn test_let_wild_yield() {
+    #[generator]
+    fn gen() -> Box<Iterator<Item=usize>> {
+        let _ = yield_!(1);
+        let _ = { yield_!(2); 3 };
+        yield_!(4);
+    }
+
+    assert_eq!(gen().collect::<Vec<_>>(), vec![1, 2, 4]);
+}
+
+#[test]
+fn test_drop_half_consumed() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    struct Guard(Rc<Cell<usize>>);
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[generator]
+    fn gen(drops: Rc<Cell<usize>>) -> Box<Iterator<Item=usize>> {
+        let guard = Guard(drops);
+        yield_!(1);
+        yield_!(2);
+    }
+
+    // Dropping a suspended generator drops the locals it's holding on to.
+    let drops = Rc::new(Cell::new(0));
+    let mut iter = gen(drops.clone());
+    assert_eq!(iter.next(), Some(1));
+    assert_eq!(drops.get(), 0);
+    drop(iter);
+    assert_eq!(drops.get(), 1);
+
+    // Running a generator to completion drops them exactly once.
+    let drops = Rc::new(Cell::new(0));
+    assert_eq!(gen(drops.clone()).collect::<Vec<_>>(), vec![1, 2]);
+    assert_eq!(drops.get(), 1);
+}
+
+#[test]
+fn test_method_call_turbofish() {
+    #[generator]
+    fn gen() -> Box<Iterator<Item=usize>> {
+        let values = { yield_!(0); vec![3, 1, 2] }.into_iter().rev().collect::<Vec<_>>();
+        for value in values {
+            yield_!(value);
+        }
+    }
+
+    assert_eq!(gen().collect::<Vec<_>>(), vec![0, 2, 1, 3]);
+}
+
+#[cfg(feature = "impl_trait")]
+#[test]
+fn test_impl_trait_for_loop() {
+    // The unboxed state machine is an `Iterator`, so the standard library's blanket
+    // `IntoIterator` impl lets it be used directly in a `for` loop.
+    #[generator]
+    fn gen(n: usize) -> impl Iterator<Item=usize> {
+        let mut i = 0;
+        while copied!(i) < copied!(n) {
+            yield_!(copied!(i));
+            i += 1;
+        }
+    }
+
+    let mut values = vec![];
+    for value in gen(3) {
+        values.push(value);
+    }
+
+    assert_eq!(values, vec![0, 1, 2]);
+}
+
+#[cfg(feature = "impl_trait")]
+#[test]
+fn test_impl_trait_arg() {
+    #[generator]
+    fn gen(iter: impl Iterator<Item=u8> + 'static) -> Box<Iterator<Item=u8>> {
+        for value in iter {
+            yield_!(value);
+        }
+    }
+
+    assert_eq!(gen(vec![1, 2, 3].into_iter()).collect::<Vec<_>>(), vec![1, 2, 3]);
+}
+
+#[test]
+fn test_expose_steps() {
+    #[generator(expose_steps)]
+    fn gen() -> Box<Iterator<Item=usize>> {
+        yield_!(1);
+        yield_!(2);
+    }
+
+    let steps = gen_steps().collect::<Vec<_>>();
+    assert_eq!(steps.len(), 3);
+
+    assert_eq!(steps[0].0, Some(1));
+    assert!(steps[0].1.starts_with("State"));
+
+    assert_eq!(steps[1].0, Some(2));
+    assert!(steps[1].1.starts_with("State"));
+    assert!(steps[0].1 != steps[1].1);
+
+    assert_eq!(steps[2], (None, "Illegal"));
+
+    // The generator itself is unaffected.
+    assert_eq!(gen().collect::<Vec<_>>(), vec![1, 2]);
+}
+
+#[test]
+fn test_match_at_binding() {
+    #[generator]
+    fn gen(values: Vec<usize>) -> Box<Iterator<Item=usize>> {
+        for value in values {
+            match value {
+                x @ 0...9 => {
+                    yield_!(x);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    assert_eq!(gen(vec![1, 20, 5]).collect::<Vec<_>>(), vec![1, 5]);
+}
+
+#[cfg(feature = "impl_trait")]
+#[test]
+fn test_clone() {
+    #[generator(clone)]
+    fn gen(n: usize) -> impl Iterator<Item=usize> + Clone {
+        let mut i = 0;
+        while copied!(i) < copied!(n) {
+            yield_!(copied!(i));
+            i += 1;
+        }
+    }
+
+    let mut iter = gen(3);
+    assert_eq!(iter.next(), Some(0));
+
+    let copy = iter.clone();
+    assert_eq!(iter.collect::<Vec<_>>(), vec![1, 2]);
+    assert_eq!(copy.collect::<Vec<_>>(), vec![1, 2]);
+}
+
+#[test]
+fn test_nested_generator_item() {
+    #[generator]
+    fn outer(n: usize) -> Box<Iterator<Item=usize>> {
+        #[generator]
+        fn inner(n: usize) -> Box<Iterator<Item=usize>> {
+            let mut i = 0;
+            while copied!(i) < copied!(n) {
+                yield_!(copied!(i));
+                i += 1;
+            }
+        }
+
+        yield_!(100);
+        yield_from!(inner(n));
+        yield_!(200);
+    }
+
+    assert_eq!(outer(3).collect::<Vec<_>>(), vec![100, 0, 1, 2, 200]);
+}
+
+#[test]
+fn test_field_of_yielding_expr() {
+    struct Point {
+        x: usize,
+        y: usize,
+    }
+
+    fn make_point() -> Point {
+        Point { x: 1, y: 2 }
+    }
+
+    #[generator]
+    fn gen() -> Box<Iterator<Item=usize>> {
+        let x = { yield_!(0); make_point() }.x;
+        yield_!(x);
+
+        let mut point = make_point();
+        point.y = 5;
+        yield_!(copied!(point.y));
+        yield_!(point.x);
+    }
+
+    assert_eq!(gen().collect::<Vec<_>>(), vec![0, 1, 5, 1]);
+}
+
+#[test]
+fn test_index_with_yielding_index() {
+    #[generator]
+    fn gen() -> Box<Iterator<Item=usize>> {
+        let mut values = vec![10, 20, 30];
+
+        let value = copied!(values[{ yield_!(0); 1 }]);
+        yield_!(value);
+
+        values[{ yield_!(1); 2 }] = 5;
+        yield_!(values[2]);
+    }
+
+    assert_eq!(gen().collect::<Vec<_>>(), vec![0, 20, 1, 5]);
+}
+
+#[test]
+fn test_for_range() {
+    #[generator]
+    fn half_open(n: usize) -> Box<Iterator<Item=usize>> {
+        'outer: for i in 0..n {
+            if copied!(i) == 5 {
+                break 'outer;
+            }
+            if copied!(i) % 2 == 0 {
+                continue;
+            }
+            for j in 0..copied!(i) {
+                yield_!(j);
+            }
+        }
+    }
+
+    let expected = (0..10)
+        .take_while(|&i| i != 5)
+        .filter(|&i| i % 2 != 0)
+        .flat_map(|i| 0..i)
+        .collect::<Vec<_>>();
+
+    assert_eq!(half_open(10).collect::<Vec<_>>(), expected);
+    assert_eq!(half_open(0).collect::<Vec<_>>(), vec![]);
+
+    #[generator]
+    fn closed(start: u8, end: u8) -> Box<Iterator<Item=u8>> {
+        for i in start...end {
+            yield_!(i);
+        }
+    }
+
+    assert_eq!(closed(1, 3).collect::<Vec<_>>(), vec![1, 2, 3]);
+    assert_eq!(closed(3, 1).collect::<Vec<_>>(), vec![]);
+    assert_eq!(closed(254, 255).collect::<Vec<_>>(), vec![254, 255]);
+
+    // Literal bounds are lowered to a counter instead.
+    #[generator]
+    fn literal() -> Box<Iterator<Item=u8>> {
+        for i in 2..4 {
+            yield_!(i);
+        }
+        for i in 254...255 {
+            yield_!(i);
+        }
+    }
+
+    assert_eq!(literal().collect::<Vec<_>>(), vec![2, 3, 254, 255]);
+}
+
+#[test]
+fn test_yield_try() {
+    use std::num::ParseIntError;
+
+    #[generator]
+    fn gen(values: Vec<&'static str>) -> Box<Iterator<Item=Result<u32, ParseIntError>>> {
+        for value in values {
+            yield_!(Ok(value.parse::<u32>()?));
+        }
+    }
+
+    let mut iter = gen(vec!["1", "2", "x", "3"]);
+    assert_eq!(iter.next(), Some(Ok(1)));
+    assert_eq!(iter.next(), Some(Ok(2)));
+    assert!(iter.next().unwrap().is_err());
+    assert_eq!(iter.next(), None);
+}
+
+#[cfg(feature = "impl_trait")]
+#[test]
+fn test_debug_state_name() {
+    use std::fmt::Debug;
+
+    struct NotDebug(usize);
+
+    #[generator(debug)]
+    fn gen() -> impl Iterator<Item=usize> + Debug {
+        let value = NotDebug(1);
+        yield_!(value.0);
+    }
+
+    let mut iter = gen();
+    assert_eq!(format!("{:?}", iter), "StateMachine { state: State0Start }");
+
+    assert_eq!(iter.next(), Some(1));
+    let debug = format!("{:?}", iter);
+    assert!(debug.starts_with("StateMachine { state: State"));
+    assert!(debug.contains("AfterSuspend"));
+
+    assert_eq!(iter.next(), None);
+    assert_eq!(format!("{:?}", iter), "StateMachine { state: Illegal }");
+}
+
+#[test]
+fn test_type_alias_item() {
+    #[generator]
+    fn gen(n: u8) -> Box<Iterator<Item=(u8, u8)>> {
+        type Pair = (u8, u8);
+
+        let pair: Pair = (copied!(n), n + 1);
+        yield_!(pair);
+    }
+
+    assert_eq!(gen(1).collect::<Vec<_>>(), vec![(1, 2)]);
+}
+
+#[test]
+fn test_yield_macro_name() {
+    #[generator(yield_macro = "emit")]
+    fn gen(n: usize) -> Box<Iterator<Item=usize>> {
+        emit!(copied!(n));
+        yield_from!(vec![n + 1, n + 2]);
+    }
+
+    assert_eq!(gen(1).collect::<Vec<_>>(), vec![1, 2, 3]);
+}
+
+#[test]
+fn test_array_repeat() {
+    #[generator]
+    fn gen(n: usize) -> Box<Iterator<Item=[usize; 3]>> {
+        yield_!([copied!(n); 3]);
+
+        // The value is only evaluated once, so this only yields a single time.
+        let array = [{ yield_!([0; 3]); n + 1 }; 3];
+        yield_!(array);
+    }
+
+    assert_eq!(gen(1).collect::<Vec<_>>(), vec![[1, 1, 1], [0, 0, 0], [2, 2, 2]]);
+}
+
+#[test]
+fn test_assert_send() {
+    #[generator(assert_send)]
+    fn gen(values: Vec<usize>) -> Box<Iterator<Item=usize>> {
+        for value in values {
+            yield_!(value);
+        }
+    }
+
+    assert_eq!(gen(vec![1, 2]).collect::<Vec<_>>(), vec![1, 2]);
+}
+
+#[test]
+fn test_while_continue_rechecks_condition() {
+    #[generator]
+    fn gen(n: usize) -> Box<Iterator<Item=usize>> {
+        let mut i = 0;
+        'outer: while copied!(i) < copied!(n) {
+            i += 1;
+            if copied!(i) % 2 == 0 {
+                continue;
+            }
+
+            let mut j = 0;
+            while { yield_!(copied!(i) * 10 + copied!(j)); copied!(j) < 1 } {
+                j += 1;
+                if copied!(i) == 5 {
+                    break 'outer;
+                }
+            }
+        }
+    }
+
+    assert_eq!(
+        gen(10).collect::<Vec<_>>(),
+        vec![10, 11, 30, 31, 50]);
+}
+
+#[test]
+fn test_unit_item() {
+    #[generator]
+    fn ticks(n: usize) -> Box<Iterator<Item=()>> {
+        let mut i = 0;
+        while copied!(i) < copied!(n) {
+            yield_!(());
+            i += 1;
+        }
+    }
+
+    assert_eq!(ticks(3).count(), 3);
+    assert_eq!(ticks(2).collect::<Vec<()>>(), vec![(), ()]);
+}
+
+#[test]
+fn test_for_destructuring() {
+    #[generator]
+    fn keys(pairs: Vec<(usize, usize)>) -> Box<Iterator<Item=usize>> {
+        for (key, value) in pairs {
+            yield_!(key);
+            yield_!(value * 10);
+        }
+    }
+
+    assert_eq!(keys(vec![(1, 2), (3, 4)]).collect::<Vec<_>>(), vec![1, 20, 3, 40]);
+
+    struct Point {
+        x: usize,
+        y: usize,
+    }
+
+    #[generator]
+    fn coords(points: Vec<Point>) -> Box<Iterator<Item=usize>> {
+        for Point { x, y: height } in points {
+            yield_!(x);
+            yield_!(height);
+        }
+    }
+
+    let points = vec![Point { x: 1, y: 2 }, Point { x: 3, y: 4 }];
+    assert_eq!(coords(points).collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+}
+
+#[test]
+fn test_loop_body_always_breaks() {
+    #[generator]
+    fn gen() -> Box<Iterator<Item=usize>> {
+        let x = loop {
+            yield_!(1);
+            break 2;
+        };
+        yield_!(x);
+
+        loop {
+            yield_!(3);
+            return;
+        }
+    }
+
+    assert_eq!(gen().collect::<Vec<_>>(), vec![1, 2, 3]);
+}
+
+#[test]
+fn test_item_type() {
+    #[generator(item = "Option<u8>")]
+    fn gen() -> Box<Iterator<Item=Option<u8>>> {
+        yield_!(Some(1));
+        yield_!(None);
+    }
+
+    assert_eq!(gen().collect::<Vec<_>>(), vec![Some(1), None]);
+}
+
+#[test]
+fn test_tuple_stmt() {
+    #[generator]
+    fn gen() -> Box<Iterator<Item=usize>> {
+        (yield_!(1), yield_!(2));
+        yield_!(3);
+    }
+
+    assert_eq!(gen().collect::<Vec<_>>(), vec![1, 2, 3]);
+}
+
+#[test]
+fn test_yield_local_in_one_branch() {
+    #[generator]
+    fn gen(flag: bool) -> Box<Iterator<Item=usize>> {
+        let y = 10;
+        if flag {
+            let x = 1;
+            yield_!(copied!(x));
+            yield_!(x + 1);
+        }
+        let x = 100;
+        yield_!(x);
+        yield_!(y);
+    }
+
+    assert_eq!(gen(true).collect::<Vec<_>>(), vec![1, 2, 100, 10]);
+    assert_eq!(gen(false).collect::<Vec<_>>(), vec![100, 10]);
+}
+
+#[test]
+fn test_yield_range() {
+    #[generator]
+    fn gen(n: usize) -> Box<Iterator<Item=::std::ops::Range<usize>>> {
+        yield_!(0..copied!(n));
+        let end = copied!(n) * 2;
+        yield_!(copied!(n)..end);
+        yield_!(0..{ yield_!(5..6); 3 });
+    }
+
+    assert_eq!(gen(2).collect::<Vec<_>>(), vec![0..2, 2..4, 5..6, 0..3]);
+}
+
+#[test]
+fn test_break_loop_from_nested_block() {
+    #[generator]
+    fn gen() -> Box<Iterator<Item=usize>> {
+        let mut i = 0;
+        'outer: loop {
+            {
+                let doubled = copied!(i) * 2;
+                {
+                    yield_!(doubled);
+                    if copied!(i) == 2 {
+                        break 'outer;
+                    }
+                }
+            }
+            i += 1;
+        }
+        yield_!(100);
+    }
+
+    assert_eq!(gen().collect::<Vec<_>>(), vec![0, 2, 4, 100]);
+}
+
+#[test]
+fn test_yielding_initializer_with_diverging_arm() {
+    #[generator]
+    fn gen(value: Result<usize, ()>) -> Box<Iterator<Item=usize>> {
+        let x = match { yield_!(1); value } {
+            Ok(x) => x,
+            Err(_) => { return; }
+        };
+        yield_!(x);
+    }
+
+    assert_eq!(gen(Ok(5)).collect::<Vec<_>>(), vec![1, 5]);
+    assert_eq!(gen(Err(())).collect::<Vec<_>>(), vec![1]);
+}
+
+#[test]
+fn test_or_pattern_bindings() {
+    enum Shape {
+        Square(usize),
+        Circle(usize),
+        Empty,
+    }
+
+    #[generator]
+    fn gen(shapes: Vec<Shape>) -> Box<Iterator<Item=usize>> {
+        for shape in shapes {
+            match shape {
+                Shape::Square(x) | Shape::Circle(x) => {
+                    yield_!(copied!(x));
+                    yield_!(x * 10);
+                }
+                Shape::Empty => {}
+            }
+        }
+    }
+
+    let shapes = vec![Shape::Square(1), Shape::Empty, Shape::Circle(2)];
+    assert_eq!(gen(shapes).collect::<Vec<_>>(), vec![1, 10, 2, 20]);
+}
+
+#[test]
+fn test_method_receiver_across_yield() {
+    #[generator]
+    fn gen() -> Box<Iterator<Item=usize>> {
+        let mut values = Vec::new();
+        for i in 0..3 {
+            copied!(values).push({ yield_!(copied!(i)); i * 10 });
+        }
+        yield_!(values.iter().sum());
+    }
+
+    assert_eq!(gen().collect::<Vec<_>>(), vec![0, 1, 2, 30]);
+}
+
+#[test]
+fn test_weak_capture() {
+    use std::rc::Rc;
+
+    #[generator]
+    fn gen(node: Rc<usize>) -> Box<Iterator<Item=Option<usize>>> {
+        let node = weak!(node);
+        loop {
+            match copied!(node).upgrade() {
+                Some(strong) => {
+                    yield_!(Some(*strong));
+                }
+                None => {
+                    yield_!(None);
+                    return;
+                }
+            }
+        }
+    }
+
+    let node = Rc::new(5);
+    let mut iter = gen(node.clone());
+    assert_eq!(iter.next(), Some(Some(5)));
+    drop(node);
+    assert_eq!(iter.next(), Some(None));
+    assert_eq!(iter.next(), None);
+}
+
+#[test]
+fn test_weak_capture_arc() {
+    use std::sync::Arc;
+    use std::thread;
+
+    #[generator]
+    fn gen(node: Arc<usize>) -> Box<Iterator<Item=Option<usize>> + Send> {
+        let node = weak!(node);
+        loop {
+            match copied!(node).upgrade() {
+                Some(strong) => {
+                    yield_!(Some(*strong));
+                }
+                None => {
+                    yield_!(None);
+                    return;
+                }
+            }
+        }
+    }
+
+    // The state only holds a `sync::Weak`, so the generator can be sent to another thread.
+    let node = Arc::new(5);
+    let mut iter = thread::spawn({
+        let node = node.clone();
+        move || {
+            let mut iter = gen(node);
+            assert_eq!(iter.next(), Some(Some(5)));
+            iter
+        }
+    }).join().unwrap();
+
+    drop(node);
+    assert_eq!(iter.next(), Some(None));
+    assert_eq!(iter.next(), None);
+}
+
+#[test]
+fn test_method_borrowing_self() {
+    struct Numbers {
+        values: Vec<usize>,
+        offset: usize,
+    }
+
+    impl Numbers {
+        #[generator]
+        fn iter(&self) -> Box<Iterator<Item=&usize>> {
+            yield_!(&copied!(self).offset);
+            for value in self.values.iter() {
+                yield_!(value);
+            }
+        }
+    }
+
+    let numbers = Numbers { values: vec![1, 2, 3], offset: 10 };
+    assert_eq!(numbers.iter().collect::<Vec<_>>(), vec![&10, &1, &2, &3]);
+}
+
+#[test]
+fn test_continue_outer_loop() {
+    #[generator]
+    fn gen() -> Box<Iterator<Item=(usize, usize)>> {
+        let mut i = 0;
+        'outer: while copied!(i) < 3 {
+            i += 1;
+            let mut j = 0;
+            loop {
+                j += 1;
+                let inner = (copied!(i), copied!(j));
+                yield_!(inner);
+                if copied!(j) == copied!(i) {
+                    continue 'outer;
+                }
+            }
+        }
+    }
+
+    assert_eq!(
+        gen().collect::<Vec<_>>(),
+        vec![(1, 1), (2, 1), (2, 2), (3, 1), (3, 2), (3, 3)]);
+}
+
+#[test]
+fn test_reserve() {
+    #[generator(reserve = "64")]
+    fn gen() -> Box<Iterator<Item=usize>> {
+        let mut acc = Vec::new();
+        copied!(acc).push(1);
+        yield_!(copied!(acc).capacity());
+        copied!(acc).push(2);
+        yield_!(copied!(acc).capacity());
+    }
+
+    // The generator doesn't know how many values it yields, so the hint isn't changed.
+    assert_eq!(gen().size_hint(), (0, None));
+
+    let capacities = gen().collect::<Vec<_>>();
+    assert_eq!(capacities.len(), 2);
+    assert!(capacities.iter().all(|&capacity| capacity >= 64));
+}
+
+#[test]
+fn test_consume_argument() {
+    #[generator]
+    fn gen(values: Vec<u8>) -> Box<Iterator<Item=u8>> {
+        for value in values {
+            yield_!(value);
+        }
+        yield_!(0);
+    }
+
+    assert_eq!(gen(vec![1, 2, 3]).collect::<Vec<_>>(), vec![1, 2, 3, 0]);
+}
+
+#[test]
+fn test_yield_block_drops_locals() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    struct Guard(Rc<Cell<usize>>);
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[generator]
+    fn gen(drops: Rc<Cell<usize>>) -> Box<Iterator<Item=usize>> {
+        yield_!({
+            let tmp = Guard(copied!(drops).clone());
+            10
+        });
+        yield_!(drops.get());
+    }
+
+    // The block's locals are dropped before the generator suspends.
+    let drops = Rc::new(Cell::new(0));
+    let mut iter = gen(drops.clone());
+    assert_eq!(iter.next(), Some(10));
+    assert_eq!(drops.get(), 1);
+    assert_eq!(iter.next(), Some(1));
+    assert_eq!(iter.next(), None);
+    assert_eq!(drops.get(), 1);
+}
+
+#[test]
+fn test_matches_yielding_scrutinee() {
+    #[generator]
+    fn gen(values: Vec<usize>) -> Box<Iterator<Item=usize>> {
+        for value in values {
+            // `yield_!` evaluates to `()`, so yield and then produce the value to match on.
+            if matches!({ yield_!(copied!(value)); value }, 1 | 2) {
+                yield_!(value * 10);
+            }
+        }
+    }
+
+    let mut gen = gen(vec![1, 2, 3]);
+    assert_eq!(gen.next(), Some(1));
+    assert_eq!(gen.next(), Some(10));
+    assert_eq!(gen.next(), Some(2));
+    assert_eq!(gen.next(), Some(20));
+    assert_eq!(gen.next(), Some(3));
+    assert_eq!(gen.next(), None);
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[test]
+fn test_inline_asm() {
+    macro_rules! nop {
+        () => { unsafe { asm!("nop" :::: "volatile") } }
+    }
+
+    #[generator]
+    fn gen() -> Box<Iterator<Item=usize>> {
+        yield_!(1);
+        nop!();
+        yield_!(2);
+    }
+
+    let mut gen = gen();
+    assert_eq!(gen.next(), Some(1));
+    assert_eq!(gen.next(), Some(2));
+    assert_eq!(gen.next(), None);
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[test]
+fn test_inline_asm_operands_across_yield() {
+    macro_rules! add_one {
+        ($value:expr) => {{
+            let out: usize;
+            unsafe { asm!("lea 1($1), $0" : "=r"(out) : "r"($value)) }
+            out
+        }}
+    }
+
+    // `asm!` reaches the generator as a macro, so its operands are only resolved in the
+    // generated code, where `x` has been restored from the state.
+    #[generator]
+    fn gen(value: usize) -> Box<Iterator<Item=usize>> {
+        let x = value;
+        yield_!(copied!(x));
+        let y = add_one!(x);
+        yield_!(y);
+    }
+
+    assert_eq!(gen(41).collect::<Vec<_>>(), vec![41, 42]);
+}
+
+#[test]
+fn test_yield_suffixed_literals() {
+    // These only wrap, or only have the given size, if the literals keep their suffixes.
+    #[generator]
+    fn gen() -> Box<Iterator<Item=i64>> {
+        yield_!(i64::from(255u8.wrapping_add(1)));
+        yield_!(i64::from(127i8.wrapping_add(1)));
+        yield_!(::std::mem::size_of_val(&5u16) as i64);
+    }
+
+    let mut gen = gen();
+    assert_eq!(gen.next(), Some(0));
+    assert_eq!(gen.next(), Some(-128));
+    assert_eq!(gen.next(), Some(2));
+    assert_eq!(gen.next(), None);
+}
+
+#[test]
+fn test_read_only_and_mutating_generators() {
+    // The step closure threads every local through the state by value, so mutating a local
+    // doesn't require anything more than `Fn` of it.
+    #[generator]
+    fn read_only(values: Vec<usize>) -> Box<Iterator<Item=usize>> {
+        let len = values.len();
+        yield_!(copied!(len));
+        yield_!(len * 2);
+    }
+
+    #[generator]
+    fn mutating(mut values: Vec<usize>) -> Box<Iterator<Item=usize>> {
+        for i in 0..3 {
+            copied!(values).push(i);
+            yield_!(copied!(values).len());
+        }
+    }
+
+    assert_eq!(read_only(vec![1, 2]).collect::<Vec<_>>(), vec![2, 4]);
+    assert_eq!(mutating(vec![7]).collect::<Vec<_>>(), vec![2, 3, 4]);
+}
+
+#[test]
+fn test_continue_in_while_condition() {
+    #[generator]
+    fn gen() -> Box<Iterator<Item=usize>> {
+        let mut counter = 0;
+        'outer: while {
+            counter += 1;
+            if copied!(counter) < 3 {
+                continue 'outer;
+            }
+            copied!(counter) < 5
+        } {
+            yield_!(copied!(counter));
+        }
+    }
+
+    assert_eq!(gen().collect::<Vec<_>>(), vec![3, 4]);
+}
+
+#[test]
+fn test_method_chain_with_yielding_arguments() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct Chain {
+        log: Rc<RefCell<Vec<usize>>>,
+        total: usize,
+    }
+
+    impl Chain {
+        fn new(log: Rc<RefCell<Vec<usize>>>) -> Self {
+            Chain { log: log, total: 0 }
+        }
+
+        fn link(mut self, tag: usize, _: ()) -> Self {
+            self.log.borrow_mut().push(tag);
+            self.total += tag;
+            self
+        }
+
+        fn total(self) -> usize {
+            self.total
+        }
+    }
+
+    #[generator]
+    fn gen(log: Rc<RefCell<Vec<usize>>>) -> Box<Iterator<Item=usize>> {
+        let total = Chain::new(log)
+            .link(1, yield_!(10))
+            .link(2, ())
+            .link(3, yield_!(30))
+            .total();
+        yield_!(total);
+    }
+
+    let log = Rc::new(RefCell::new(vec![]));
+    let mut gen = gen(log.clone());
+
+    // Each link runs only once its arguments are evaluated, and before the next link's.
+    assert_eq!(gen.next(), Some(10));
+    assert_eq!(*log.borrow(), vec![]);
+    assert_eq!(gen.next(), Some(30));
+    assert_eq!(*log.borrow(), vec![1, 2]);
+    assert_eq!(gen.next(), Some(6));
+    assert_eq!(*log.borrow(), vec![1, 2, 3]);
+    assert_eq!(gen.next(), None);
+}
+
+#[test]
+fn test_trait_default_method() {
+    trait Countdown {
+        type Item;
+
+        fn start(&self) -> usize;
+        fn item(&self) -> Self::Item;
+
+        #[generator]
+        fn countdown(&self) -> Box<Iterator<Item=(usize, Self::Item)>> where Self: Sized {
+            let mut n = copied!(self).start();
+            while copied!(n) > 0 {
+                yield_!((copied!(n), copied!(self).item()));
+                n -= 1;
+            }
+        }
+    }
+
+    struct Launch;
+
+    impl Countdown for Launch {
+        type Item = &'static str;
+
+        fn start(&self) -> usize { 2 }
+        fn item(&self) -> &'static str { "go" }
+    }
+
+    assert_eq!(Launch.countdown().collect::<Vec<_>>(), vec![(2, "go"), (1, "go")]);
+}
+
+#[test]
+fn test_straight_line_generator() {
+    // Every state ends in a yield or a return, so the step function has no loop.
+    #[generator]
+    fn gen(value: usize) -> Box<Iterator<Item=usize>> {
+        yield_!(value);
+    }
+
+    let mut gen = gen(7);
+    assert_eq!(gen.next(), Some(7));
+    assert_eq!(gen.next(), None);
+    assert_eq!(gen.next(), None);
+}
+
+#[test]
+fn test_if_let_yielding_scrutinee() {
+    fn half(value: usize) -> Option<usize> {
+        if value % 2 == 0 { Some(value / 2) } else { None }
+    }
+
+    #[generator]
+    fn gen(values: Vec<usize>) -> Box<Iterator<Item=usize>> {
+        for value in values {
+            if let Some(x) = { yield_!(0); half(copied!(value)) } {
+                yield_!(x);
+            } else {
+                yield_!(value);
+            }
+        }
+    }
+
+    assert_eq!(gen(vec![4, 3]).collect::<Vec<_>>(), vec![0, 2, 0, 3]);
+}
+
+#[test]
+fn test_yield_from_chars_of_each_word() {
+    #[generator]
+    fn gen(words: Vec<&'static str>) -> Box<Iterator<Item=char>> {
+        for word in words {
+            yield_from!(word.chars());
+        }
+    }
+
+    assert_eq!(gen(vec!["ab", "", "c"]).collect::<String>(), "abc");
+}
+
+#[test]
+fn test_yield_references_into_borrowed_collection() {
+    // The collection is borrowed from the caller rather than owned by the generator, so each
+    // element reference outlives the iteration that produced it.
+    #[generator]
+    fn gen<'a>(values: &'a Vec<String>) -> Box<Iterator<Item=&'a String> + 'a> {
+        for value in values {
+            yield_!(value);
+        }
+    }
+
+    let values = vec!["a".to_string(), "b".to_string()];
+    let refs = gen(&values).collect::<Vec<_>>();
+    assert_eq!(refs, vec![&values[0], &values[1]]);
+}
+
+#[test]
+fn test_struct_update_with_yielding_field() {
+    #[derive(Debug, PartialEq)]
+    struct Point {
+        x: usize,
+        y: usize,
+        z: usize,
+    }
+
+    #[generator]
+    fn gen(base: Point) -> Box<Iterator<Item=Point>> {
+        let point = Point { x: { yield_!(Point { x: 0, y: 0, z: 0 }); 5 }, ..base };
+        yield_!(point);
+    }
+
+    let points = gen(Point { x: 1, y: 2, z: 3 }).collect::<Vec<_>>();
+    assert_eq!(points, vec![Point { x: 0, y: 0, z: 0 }, Point { x: 5, y: 2, z: 3 }]);
+}
+
+#[test]
+fn test_loop_exited_only_by_return() {
+    #[generator]
+    fn gen(limit: usize) -> Box<Iterator<Item=usize>> {
+        let mut i = 0;
+        loop {
+            if copied!(i) == copied!(limit) {
+                return;
+            }
+            yield_!(copied!(i));
+            i += 1;
+        }
+    }
+
+    let mut gen = gen(2);
+    assert_eq!(gen.next(), Some(0));
+    assert_eq!(gen.next(), Some(1));
+    assert_eq!(gen.next(), None);
+    assert_eq!(gen.next(), None);
+}
+
+#[test]
+fn test_method_consuming_self() {
+    struct Countdown {
+        label: String,
+        n: usize,
+    }
+
+    impl Countdown {
+        #[generator]
+        fn into_labels(mut self) -> Box<Iterator<Item=String>> {
+            while copied!(self.n) > 0 {
+                yield_!(copied!(self.n).to_string());
+                self.n -= 1;
+            }
+
+            let Countdown { label, .. } = self;
+            yield_!(label);
+        }
+    }
+
+    let countdown = Countdown { label: "go".to_string(), n: 2 };
+    assert_eq!(countdown.into_labels().collect::<Vec<_>>(), vec!["2", "1", "go"]);
+}
+
+#[test]
+fn test_empty_match_over_empty_enum() {
+    enum Void {}
+
+    #[generator]
+    fn gen(values: Vec<Result<usize, Void>>) -> Box<Iterator<Item=usize>> {
+        for value in values {
+            let value = match value {
+                Ok(value) => value,
+                Err(void) => match void {},
+            };
+            yield_!(value);
+        }
+    }
+
+    assert_eq!(gen(vec![Ok(1), Ok(2)]).collect::<Vec<_>>(), vec![1, 2]);
+}
+
+#[test]
+fn test_method_assigning_own_field() {
+    struct Counter {
+        count: usize,
+    }
+
+    impl Counter {
+        #[generator]
+        fn ticks(&mut self, n: usize) -> Box<Iterator<Item=usize>> {
+            for _ in 0..n {
+                self.count += 1;
+                yield_!(copied!(self.count));
+            }
+        }
+    }
+
+    let mut counter = Counter { count: 10 };
+    assert_eq!(counter.ticks(3).collect::<Vec<_>>(), vec![11, 12, 13]);
+    assert_eq!(counter.count, 13);
+}
+
+#[test]
+fn test_yield_try_converts_error() {
+    use std::num::ParseIntError;
+
+    #[derive(Debug, PartialEq)]
+    enum Error {
+        Parse,
+    }
+
+    impl From<ParseIntError> for Error {
+        fn from(_: ParseIntError) -> Self {
+            Error::Parse
+        }
+    }
+
+    #[generator]
+    fn gen(values: Vec<&'static str>) -> Box<Iterator<Item=Result<u32, Error>>> {
+        for value in values {
+            let parsed = try!(value.parse::<u32>());
+            yield_!(Ok(parsed));
+        }
+    }
+
+    let items = gen(vec!["1", "2", "x", "3"]).collect::<Vec<_>>();
+    assert_eq!(items, vec![Ok(1), Ok(2), Err(Error::Parse)]);
+}
+
+#[test]
+fn test_while_let_reevaluates_scrutinee() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    struct Counted {
+        next: usize,
+        end: usize,
+        calls: Rc<Cell<usize>>,
+    }
+
+    impl Iterator for Counted {
+        type Item = usize;
+
+        fn next(&mut self) -> Option<usize> {
+            self.calls.set(self.calls.get() + 1);
+            if self.next < self.end {
+                self.next += 1;
+                Some(self.next - 1)
+            } else {
+                None
+            }
+        }
+    }
+
+    #[generator]
+    fn gen(mut iter: Counted) -> Box<Iterator<Item=usize>> {
+        while let Some(x) = copied!(iter).next() {
+            yield_!(x);
+        }
+    }
+
+    let calls = Rc::new(Cell::new(0));
+    let mut gen = gen(Counted { next: 0, end: 2, calls: calls.clone() });
+
+    // The scrutinee runs once per iteration, plus once more to find the end.
+    assert_eq!(gen.next(), Some(0));
+    assert_eq!(calls.get(), 1);
+    assert_eq!(gen.next(), Some(1));
+    assert_eq!(calls.get(), 2);
+    assert_eq!(gen.next(), None);
+    assert_eq!(calls.get(), 3);
+}
+
+#[should_panic(expected = "gave up after 1")]
+#[test]
+fn test_diverging_call() {
+    #[generator]
+    fn gen() -> Box<Iterator<Item=usize>> {
+        fn give_up(value: usize) -> ! {
+            panic!("gave up after {}", value)
+        }
+
+        yield_!(1);
+        give_up(1);
+        yield_!(2);
+    }
+
+    let mut gen = gen();
+    assert_eq!(gen.next(), Some(1));
+    gen.next();
+}
+
+#[test]
+fn test_call_local_closure() {
+    #[generator]
+    fn gen(offset: usize) -> Box<Iterator<Item=usize>> {
+        let add = move |x: usize| x + offset;
+        let y = add({ yield_!(3); 3 });
+        yield_!(y);
+    }
+
+    assert_eq!(gen(10).collect::<Vec<_>>(), vec![3, 13]);
+}
+
+#[test]
+fn test_let_match_with_yielding_arm() {
+    #[derive(Clone, Copy)]
+    enum Choice {
+        A,
+        B,
+    }
+
+    #[generator]
+    fn gen(x: Choice) -> Box<Iterator<Item=usize>> {
+        let y = match x {
+            Choice::A => { yield_!(1); 1 }
+            Choice::B => 2,
+        };
+        yield_!(y * 10);
+    }
+
+    assert_eq!(gen(Choice::A).collect::<Vec<_>>(), vec![1, 10]);
+    assert_eq!(gen(Choice::B).collect::<Vec<_>>(), vec![20]);
+}
+
+#[test]
+fn test_poisoned_after_panic() {
+    use std::panic::{self, AssertUnwindSafe};
+
+    #[generator]
+    fn gen(fail: bool) -> Box<Iterator<Item=usize>> {
+        yield_!(1);
+        if copied!(fail) {
+            panic!("step failed");
+        }
+        yield_!(2);
+    }
+
+    let mut gen = gen(true);
+    assert_eq!(gen.next(), Some(1));
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| gen.next()));
+    assert!(result.is_err());
+
+    // The generator neither restarts nor panics again.
+    assert_eq!(gen.next(), None);
+    assert_eq!(gen.next(), None);
+}
+
+#[test]
+fn test_context() {
+    struct Config {
+        step: usize,
+    }
+
+    #[generator(context = "Config")]
+    fn gen(n: usize) -> Box<Iterator<Item=usize>> {
+        let mut i = 0;
+        while copied!(i) < copied!(n) {
+            let step = ctx!().step;
+            yield_!(copied!(i) * step);
+            i += 1;
+        }
+    }
+
+    assert_eq!(gen(3, Config { step: 10 }).collect::<Vec<_>>(), vec![0, 10, 20]);
+}
+
+#[test]
+fn test_borrowed_context() {
+    struct Config {
+        step: usize,
+    }
+
+    #[generator(context = "&'a Config")]
+    fn gen<'a>(n: usize) -> Box<Iterator<Item=usize> + 'a> {
+        let mut i = 0;
+        while copied!(i) < copied!(n) {
+            let step = ctx!().step;
+            yield_!(copied!(i) * step);
+            i += 1;
+        }
+    }
+
+    // The generator only borrows the config, so it's still ours afterwards.
+    let config = Config { step: 10 };
+    assert_eq!(gen(3, &config).collect::<Vec<_>>(), vec![0, 10, 20]);
+    assert_eq!(config.step, 10);
+}
+
+#[test]
+fn test_tuple_field_read_after_yield() {
+    #[generator]
+    fn gen(pair: (usize, usize)) -> Box<Iterator<Item=usize>> {
+        yield_!(0);
+        let a = copied!(pair.0);
+        yield_!(a);
+        let b = pair.1;
+        yield_!(b);
+    }
+
+    assert_eq!(gen((1, 2)).collect::<Vec<_>>(), vec![0, 1, 2]);
+}
+
+#[test]
+fn test_recompute() {
+    use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+    static EVALUATIONS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    fn square(n: usize) -> usize {
+        EVALUATIONS.fetch_add(1, Ordering::SeqCst);
+        n * n
+    }
+
+    #[generator]
+    fn gen(n: usize) -> Box<Iterator<Item=usize>> {
+        let squared = recompute!(square(n));
+        yield_!(copied!(squared));
+        yield_!(squared + 1);
+    }
+
+    assert_eq!(gen(3).collect::<Vec<_>>(), vec![9, 10]);
+
+    // Once when it's declared, and again when resuming after the first yield.
+    assert_eq!(EVALUATIONS.load(Ordering::SeqCst), 2);
+}
+
+#[cfg(feature = "impl_trait")]
+#[test]
+fn test_recompute_is_not_captured() {
+    use std::mem;
+
+    #[generator]
+    fn gen(n: usize) -> impl Iterator<Item=usize> {
+        let table = recompute!([n; 512]);
+        yield_!(copied!(table[0]));
+        yield_!(copied!(table[511]) + 1);
+    }
+
+    let iter = gen(7);
+    assert!(mem::size_of_val(&iter) < 512 * mem::size_of::<usize>());
+    assert_eq!(iter.collect::<Vec<_>>(), vec![7, 8]);
+}
+
+#[test]
+fn test_closure_with_loops_and_try() {
+    #[generator]
+    fn gen(limit: usize) -> Box<Iterator<Item=usize>> {
+        let digits = String::from("7");
+        let sum = move |n: usize| -> Result<usize, ::std::num::ParseIntError> {
+            let mut total = digits.parse::<usize>()?;
+            for i in 0..n {
+                total += i;
+            }
+            let mut j = 0;
+            while j < n {
+                total += 1;
+                j += 1;
+            }
+            Ok(total)
+        };
+        yield_!(0);
+        yield_!(sum(limit).unwrap());
+    }
+
+    // The closure keeps its own loops and `?`, and `digits` is moved into it, so it's not
+    // captured by the state after the first yield.
+    assert_eq!(gen(3).collect::<Vec<_>>(), vec![0, 7 + 3 + 3]);
+}
+
+#[test]
+fn test_move_closure_keeps_copy_and_inner_locals() {
+    #[generator]
+    fn gen() -> Box<Iterator<Item=usize>> {
+        let offset = 1;
+        let label = String::from("label");
+        let add = move |x: usize| {
+            // This `label` is the closure's own, so the outer one isn't moved.
+            let label = x;
+            label + offset
+        };
+        yield_!(add(1));
+        yield_!(offset);
+        yield_!(label.len());
+    }
+
+    // `offset` is copied into the closure, so it's still in the state after the first yield.
+    assert_eq!(gen().collect::<Vec<_>>(), vec![2, 1, 5]);
+}
+
+#[test]
+fn test_diverging_fn_shadowed() {
+    #[generator]
+    fn gen() -> Box<Iterator<Item=usize>> {
+        fn fail(value: usize) -> usize {
+            value
+        }
+
+        {
+            fn fail(value: usize) -> ! {
+                panic!("failed with {}", value)
+            }
+        }
+
+        // The diverging `fail` is only in scope in the block above.
+        fail(1);
+        yield_!(1);
+
+        fn give_up(value: usize) -> ! {
+            panic!("gave up after {}", value)
+        }
+
+        let give_up = |value: usize| value;
+        give_up(2);
+        yield_!(2);
+    }
+
+    assert_eq!(gen().collect::<Vec<_>>(), vec![1, 2]);
+}
+
+#[test]
+fn test_range_counter_state_size() {
+    #[generator]
+    fn range_counter_literal() -> Box<Iterator<Item=usize>> {
+        for i in 0..1000 {
+            yield_!(i);
+        }
+    }
+
+    #[generator]
+    fn range_into_iter_literal() -> Box<Iterator<Item=usize>> {
+        for i in ::std::iter::IntoIterator::into_iter(0..1000) {
+            yield_!(i);
+        }
+    }
+
+    // With a literal end, the counter lowering only carries the index across each yield, while the
+    // generic lowering carries the whole `Range`.
+    let counter = ::std::mem::size_of_val(&*range_counter_literal());
+    let into_iter = ::std::mem::size_of_val(&*range_into_iter_literal());
+    assert!(counter < into_iter, "{} >= {}", counter, into_iter);
+}
+
+#[test]
+fn test_on_resume_hook_in_loop() {
+    use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+    static RESUMES: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    fn count_resume() {
+        RESUMES.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[generator(on_resume = "count_resume")]
+    fn gen(count: usize) -> Box<Iterator<Item=usize>> {
+        let mut i = 0;
+        loop {
+            if copied!(i) == copied!(count) {
+                return;
+            }
+            i += 1;
+            yield_!(copied!(i));
+        }
+    }
+
+    let mut iter = gen(3);
+    assert_eq!(RESUMES.load(Ordering::SeqCst), 0);
+    assert_eq!(iter.next(), Some(1));
+    assert_eq!(RESUMES.load(Ordering::SeqCst), 0);
+
+    assert_eq!(iter.collect::<Vec<_>>(), vec![2, 3]);
+    assert_eq!(RESUMES.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn test_tuple_stmt_drop_order() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct Noisy(&'static str, Rc<RefCell<Vec<&'static str>>>);
+
+    impl Drop for Noisy {
+        fn drop(&mut self) {
+            self.1.borrow_mut().push(self.0);
+        }
+    }
+
+    #[generator]
+    fn gen(log: Rc<RefCell<Vec<&'static str>>>) -> Box<Iterator<Item=usize>> {
+        (
+            Noisy("first", copied!(log).clone()),
+            {
+                yield_!(1);
+                copied!(log).borrow_mut().push("evaluated");
+                Noisy("second", copied!(log).clone())
+            },
+        );
+        yield_!(2);
+    }
+
+    // Like rustc, the fields are dropped left to right once the whole tuple has been evaluated.
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let mut iter = gen(log.clone());
+    assert_eq!(iter.next(), Some(1));
+    assert!(log.borrow().is_empty());
+    assert_eq!(iter.next(), Some(2));
+    assert_eq!(*log.borrow(), vec!["evaluated", "first", "second"]);
+}
+
+#[cfg(feature = "impl_trait")]
+#[test]
+fn test_recursive_call_not_held_across_yield() {
+    #[generator]
+    fn countdown(n: usize) -> impl Iterator<Item=usize> {
+        if copied!(n) > 0 {
+            // The recursive state machine is used up before we suspend, so it isn't stored.
+            let remaining = countdown(copied!(n) - 1).count();
+            yield_!(remaining + 1);
+            yield_!(n);
+        }
+    }
+
+    assert_eq!(countdown(3).collect::<Vec<_>>(), vec![3, 3]);
+}
+
+#[test]
+fn test_item_type_yield_from_and_try() {
+    use std::fmt::Debug;
+
+    // The values from `yield_from!` are coerced to the item type too.
+    #[generator(item = "Box<Debug>")]
+    fn gen() -> Box<Iterator<Item=Box<Debug>>> {
+        yield_!(Box::new(1));
+        yield_from!(vec![Box::new("two")]);
+    }
+
+    let items = gen().map(|item| format!("{:?}", item)).collect::<Vec<_>>();
+    assert_eq!(items, vec!["1", "\"two\""]);
+
+    #[generator(item = "Result<u8, String>")]
+    fn check(value: Result<u8, &'static str>) -> Box<Iterator<Item=Result<u8, String>>> {
+        yield_!(Ok(value?));
+    }
+
+    assert_eq!(check(Ok(1)).collect::<Vec<_>>(), vec![Ok(1)]);
+    assert_eq!(check(Err("bad")).collect::<Vec<_>>(), vec![Err("bad".to_string())]);
+}
+
 /*
 #[test]
 fn test_shadowing() {
@@ -0,0 +1,18 @@
+#![cfg(feature = "trace")]
+
+mod support;
+
+#[test]
+fn test_trace_block_names() {
+    let output = support::rustc("tests/trace/loop.rs", &["-Z", "no-trans"]);
+
+    assert!(output.status.success());
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert!(stderr.contains("stateful: start_new_block: block=bb0 name=Some(\"Start\")"));
+    assert!(stderr.contains("name=Some(\"Loop\")"));
+    assert!(stderr.contains("name=Some(\"LoopExit\")"));
+    assert!(stderr.contains("stateful: terminate: block="));
+    assert!(stderr.contains("stateful: state_arm: block="));
+}
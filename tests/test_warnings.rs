@@ -0,0 +1,33 @@
+mod support;
+
+#[test]
+fn test_warn_unused_capture() {
+    let output = support::rustc("tests/warnings/unused_capture.rs", &["-Z", "no-trans"]);
+
+    assert!(output.status.success());
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert!(stderr.contains(
+        "warning: `unused` is captured across a suspension point but never used afterwards"));
+    assert!(!stderr.contains("`_ignored`"));
+    assert!(!stderr.contains("`n` is captured"));
+    assert!(!stderr.contains("`allowed_guard`"));
+    assert!(!stderr.contains("`default_guard`"));
+}
+
+#[test]
+fn test_size_report() {
+    let output = support::rustc("tests/warnings/size_report.rs", &["-Z", "no-trans"]);
+
+    assert!(output.status.success());
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    let largest = stderr.lines()
+        .find(|line| line.starts_with("note: the largest state is"))
+        .expect("missing the largest state");
+
+    assert!(largest.contains("`small: _`"));
+    assert!(largest.contains("`big: [u8; 64]`"));
+}
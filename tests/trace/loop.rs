@@ -0,0 +1,13 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator]
+fn gen(n: usize) -> Box<Iterator<Item=usize>> {
+    let mut i = 0;
+    while copied!(i) < copied!(n) {
+        yield_!(copied!(i));
+        i += 1;
+    }
+}
+
+fn main() {}
@@ -0,0 +1,13 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator(size_report)]
+fn gen() -> Box<Iterator<Item=u8>> {
+    let small = 1u8;
+    yield_!(copied!(small));
+    let big: [u8; 64] = [0; 64];
+    yield_!(copied!(small));
+    yield_!(big[0] + small);
+}
+
+fn main() {}
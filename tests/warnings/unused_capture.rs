@@ -0,0 +1,27 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator(warn_unused_captures)]
+fn gen(n: usize) -> Box<Iterator<Item=usize>> {
+    let unused = vec![1, 2, 3];
+    let _ignored = vec![4, 5, 6];
+    yield_!(copied!(n));
+    yield_!(n + 1);
+}
+
+#[generator(warn_unused_captures)]
+#[allow(unused_variables)]
+fn allowed(n: usize) -> Box<Iterator<Item=usize>> {
+    let allowed_guard = vec![1, 2, 3];
+    yield_!(copied!(n));
+    yield_!(n + 1);
+}
+
+#[generator]
+fn not_opted_in(n: usize) -> Box<Iterator<Item=usize>> {
+    let default_guard = vec![1, 2, 3];
+    yield_!(copied!(n));
+    yield_!(n + 1);
+}
+
+fn main() {}